@@ -9,11 +9,25 @@ extern "C" {
     fn our_code_starts_here(input: u64) -> u64;
 }
 
+// Error codes passed to snek_error by the generated code. The program exits
+// with the same code, so each category of error can be told apart.
+const ERR_OVERFLOW: i64 = 1;
+const ERR_INVALID_ARGUMENT: i64 = 2;
+const ERR_INDEX_OUT_OF_BOUNDS: i64 = 3;
+// Exit status for a malformed command line, as in sysexits.h.
+const EXIT_USAGE: i32 = 64;
+
 #[export_name = "\x01snek_error"]
 pub extern "C" fn snek_error(errcode: i64) {
-    // TODO: print error message according to writeup
-    eprintln!("an error ocurred {errcode}");
-    std::process::exit(1);
+    let msg = match errcode {
+        ERR_OVERFLOW => "overflow",
+        ERR_INVALID_ARGUMENT => "invalid argument",
+        ERR_INDEX_OUT_OF_BOUNDS => "index out of bounds",
+        _ => "unknown error",
+    };
+    eprintln!("an error occurred: {msg}");
+    let exit_code = if (1..=255).contains(&errcode) { errcode as i32 } else { 255 };
+    std::process::exit(exit_code);
 }
 
 fn parse_input(input: &str) -> u64 {
@@ -21,10 +35,39 @@ fn parse_input(input: &str) -> u64 {
     0
 }
 
+// Picks the input argument out of the command line. When no input is given,
+// it defaults to the value passed with `--input-default <value>`, or to 0.
+// Anything after the input is ignored. Fails if `--input-default` has no value.
+fn input_arg(args: &[String]) -> Result<&str, String> {
+    let mut default = "0";
+    let mut input = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--input-default" {
+            match args.next() {
+                Some(value) => default = value,
+                None => return Err("--input-default needs a value".to_string()),
+            }
+        } else if input.is_none() {
+            input = Some(arg.as_str());
+        }
+    }
+    Ok(input.unwrap_or(default))
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let input = if args.len() == 2 { &args[1] } else { "false" };
-    let input = parse_input(&input);
+    let input = match input_arg(&args[1..]) {
+        Ok(input) => input,
+        Err(msg) => {
+            eprintln!(
+                "{msg}\nusage: {} [--input-default <value>] [input]",
+                args[0]
+            );
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    let input = parse_input(input);
 
     let i: u64 = unsafe { our_code_starts_here(input) };
     println!("{i}");
@@ -1,13 +1,560 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+use clap::{Parser, Subcommand, ValueEnum};
+use sexp::*;
+
+/// Definitions that every program gets, ahead of its own.
+static PRELUDE: &str = include_str!("prelude.snek");
+
+/// Compiles a Diamondback program to x86-64 assembly.
+///
+/// The legacy `diamondback input.snek output.s` form still works.
+#[derive(Parser, Debug)]
+#[command(
+    name = "diamondback",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Cmd>,
+
+    /// The .snek program to compile
+    #[arg(required_unless_present = "test_dir")]
+    source: Option<String>,
+
+    /// Where to write the output (same as -o)
+    #[arg(conflicts_with = "output")]
+    legacy_output: Option<String>,
+
+    /// Where to write the output; it goes to stdout if not given
+    #[arg(short = 'o', value_name = "PATH")]
+    output: Option<String>,
+
+    /// The compilation stage to write out
+    #[arg(long, value_enum, default_value_t = Emit::Asm)]
+    emit: Emit,
+
+    /// How to report errors on stderr
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human, global = true)]
+    error_format: ErrorFormat,
+
+    /// Report errors on stderr as JSON objects (same as --error-format json)
+    #[arg(long, global = true)]
+    json_errors: bool,
+
+    /// Assemble, link and run the program instead of writing it out
+    #[arg(long, conflicts_with_all = ["legacy_output", "output", "emit"])]
+    run: bool,
+
+    /// The input to pass to the program with --run
+    #[arg(long, value_name = "VALUE", requires = "run")]
+    input: Option<String>,
+
+    /// Run every NAME.snek in DIR that has a NAME.out or NAME.err next to it,
+    /// and report which ones pass
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["source", "legacy_output", "output", "emit", "run"]
+    )]
+    test_dir: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Report front-end errors without generating any assembly
+    Check {
+        /// The .snek program to check
+        source: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Emit {
+    /// The parsed program, as an s-expression
+    Ast,
+    /// x86-64 assembly for nasm
+    Asm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ErrorFormat {
+    /// The error message, as text
+    Human,
+    /// One JSON object per error, with its code, message, span and severity
+    Json,
+}
+
+/// A static error in a program. Each kind of error has a code that tools
+/// can rely on; codes are never reused once removed.
+#[derive(Debug, PartialEq)]
+enum CompileError {
+    /// E0001: the program has no expressions, only whitespace and comments.
+    EmptyProgram,
+    /// E0002: the program text is not a well-formed s-expression.
+    Parse {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    /// E0003: a `#|` block comment is never closed with `|#`.
+    UnterminatedComment { line: usize, column: usize },
+    /// E0004: a number literal does not fit in 64 bits.
+    NumberOutOfRange(String),
+    /// E0005: an `include` form is malformed, its file can't be read, or the
+    /// file holds something other than definitions.
+    Include { path: String, reason: String },
+    /// E0006: files include each other in a cycle.
+    IncludeCycle(Vec<String>),
+    /// E0007: two files define a function with the same name.
+    DuplicateDefinition {
+        name: String,
+        first: String,
+        second: String,
+    },
+    /// An error in an included file. It has the code and span of the
+    /// underlying error.
+    InFile {
+        file: String,
+        error: Box<CompileError>,
+    },
+}
+
+impl CompileError {
+    fn code(&self) -> &'static str {
+        match self {
+            CompileError::EmptyProgram => "E0001",
+            CompileError::Parse { .. } => "E0002",
+            CompileError::UnterminatedComment { .. } => "E0003",
+            CompileError::NumberOutOfRange(_) => "E0004",
+            CompileError::Include { .. } => "E0005",
+            CompileError::IncludeCycle(_) => "E0006",
+            CompileError::DuplicateDefinition { .. } => "E0007",
+            CompileError::InFile { error, .. } => error.code(),
+        }
+    }
+
+    /// The 1-based line and column the error points at, if it has one.
+    fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            CompileError::Parse { line, column, .. }
+            | CompileError::UnterminatedComment { line, column } => Some((*line, *column)),
+            CompileError::InFile { error, .. } => error.span(),
+            _ => None,
+        }
+    }
+
+    /// The included file the error is in, if it isn't in the main program.
+    fn file(&self) -> Option<&str> {
+        match self {
+            CompileError::InFile { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompileError::EmptyProgram => write!(f, "Invalid: empty program"),
+            CompileError::Parse {
+                message,
+                line,
+                column,
+            } => write!(f, "Invalid: parse error {line}:{column}: {message}"),
+            CompileError::UnterminatedComment { line, column } => {
+                write!(f, "Invalid: unterminated block comment at {line}:{column}")
+            }
+            CompileError::NumberOutOfRange(literal) => {
+                write!(f, "Invalid: number literal {literal} is out of range")
+            }
+            CompileError::Include { path, reason } => {
+                write!(f, "Invalid: cannot include {path}: {reason}")
+            }
+            CompileError::IncludeCycle(files) => {
+                write!(f, "Invalid: include cycle {}", files.join(" -> "))
+            }
+            CompileError::DuplicateDefinition {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "Invalid: function {name} is defined in both {first} and {second}"
+            ),
+            CompileError::InFile { file, error } => write!(f, "{file}: {error}"),
+        }
+    }
+}
+
+/// Removes comments from the program text: `;` line comments and `#| ... |#`
+/// block comments, which may be nested. Comment markers inside a `"..."`
+/// string literal are left untouched. Block comments are replaced by spaces
+/// with their newlines kept, so the remaining text has every character at the
+/// same line and column as in the source file.
+fn strip_comments(text: &str) -> Result<String, CompileError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+    // How deeply nested the current block comment is, and where the
+    // outermost one started.
+    let mut block_depth = 0;
+    let mut block_start = 0;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        i += 1;
+        if block_depth > 0 {
+            if (c == '|' && next == Some('#')) || (c == '#' && next == Some('|')) {
+                block_depth = if c == '|' {
+                    block_depth - 1
+                } else {
+                    block_depth + 1
+                };
+                out.push_str("  ");
+                i += 1;
+            } else {
+                out.push(if c == '\n' { c } else { ' ' });
+            }
+            continue;
+        }
+        if in_comment {
+            if c == '\n' {
+                in_comment = false;
+                out.push(c);
+            }
+            continue;
+        }
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            out.push(c);
+            continue;
+        }
+        match c {
+            ';' => in_comment = true,
+            '#' if next == Some('|') => {
+                block_depth = 1;
+                block_start = i - 1;
+                out.push_str("  ");
+                i += 1;
+            }
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+
+    if block_depth > 0 {
+        let before = &chars[..block_start];
+        let line_start = before.iter().rposition(|&c| c == '\n').map_or(0, |n| n + 1);
+        return Err(CompileError::UnterminatedComment {
+            line: before.iter().filter(|&&c| c == '\n').count() + 1,
+            column: block_start - line_start + 1,
+        });
+    }
+    Ok(out)
+}
+
+/// Reads a number literal that sexp leaves as a symbol: hexadecimal with a
+/// `0x` prefix, or decimal or hexadecimal with `_` digit separators, either
+/// optionally negative. Returns None for anything that isn't one.
+fn number_literal(s: &str) -> Option<Result<i64, CompileError>> {
+    let (sign, rest) = match s.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", s),
+    };
+    let (radix, digits) = match rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")) {
+        Some(digits) => (16, digits),
+        None => (10, rest),
+    };
+    if !digits.starts_with(|c: char| c.is_digit(radix))
+        || !digits.chars().all(|c| c == '_' || c.is_digit(radix))
+    {
+        return None;
+    }
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    Some(
+        i64::from_str_radix(&format!("{sign}{digits}"), radix)
+            .map_err(|_| CompileError::NumberOutOfRange(s.to_string())),
+    )
+}
+
+/// Turns the number literals that sexp reads as symbols into numbers.
+fn read_number_literals(sexp: Sexp) -> Result<Sexp, CompileError> {
+    match sexp {
+        Sexp::Atom(Atom::S(s)) => match number_literal(&s) {
+            Some(n) => Ok(Sexp::Atom(Atom::I(n?))),
+            None => Ok(Sexp::Atom(Atom::S(s))),
+        },
+        Sexp::List(items) => Ok(Sexp::List(
+            items
+                .into_iter()
+                .map(read_number_literals)
+                .collect::<Result<_, _>>()?,
+        )),
+        atom => Ok(atom),
+    }
+}
+
+fn parse_program(text: &str) -> Result<Sexp, CompileError> {
+    let text = strip_comments(text)?;
+    if text.trim().is_empty() {
+        return Err(CompileError::EmptyProgram);
+    }
+    // Wrap the program in parens so that the definitions and the main
+    // expression are read as a single s-expression. sexp counts columns from
+    // 0, and on the first line the extra paren already adds one.
+    let prog = parse(&format!("({text})")).map_err(|e| CompileError::Parse {
+        message: e.message.to_string(),
+        line: e.line,
+        column: if e.line == 1 {
+            e.column.max(1)
+        } else {
+            e.column + 1
+        },
+    })?;
+    read_number_literals(prog)
+}
+
+/// The name defined by a `(fun (name args...) body)` definition.
+fn definition_name(item: &Sexp) -> Option<&str> {
+    match item {
+        Sexp::List(items) => match &items[..] {
+            [Sexp::Atom(Atom::S(keyword)), Sexp::List(signature), ..] if keyword == "fun" => {
+                match signature.first() {
+                    Some(Sexp::Atom(Atom::S(name))) => Some(name),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-    let in_name = &args[1];
-    let out_name = &args[2];
+/// What `expand_includes` has seen so far.
+#[derive(Default)]
+struct Includes {
+    /// The files being included, outermost first.
+    stack: Vec<PathBuf>,
+    /// Every file included so far. A file that is included again, say by two
+    /// libraries that both use it, adds nothing the second time.
+    done: HashSet<PathBuf>,
+    /// The file that defines each function.
+    defined: HashMap<String, PathBuf>,
+    /// The errors found so far. Expansion carries on past each one, so that
+    /// every error is reported and not just the first.
+    errors: Vec<CompileError>,
+}
+
+/// Replaces each top-level `(include "file.snek")` in the program read from
+/// `path` with the definitions in that file, which is found relative to
+/// `path`. Errors are added to `includes.errors`, leaving out the form that
+/// has the error.
+fn expand_includes(prog: Sexp, path: &Path, includes: &mut Includes) -> Vec<Sexp> {
+    let items = match prog {
+        Sexp::List(items) => items,
+        atom => vec![atom],
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        let include = match &item {
+            Sexp::List(form) => match &form[..] {
+                [Sexp::Atom(Atom::S(keyword)), rest @ ..] if keyword == "include" => Some(rest),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(include) = include else {
+            if let Some(name) = definition_name(&item) {
+                match includes.defined.get(name) {
+                    Some(first) if first != path => {
+                        includes.errors.push(CompileError::DuplicateDefinition {
+                            name: name.to_string(),
+                            first: first.display().to_string(),
+                            second: path.display().to_string(),
+                        });
+                        continue;
+                    }
+                    _ => {
+                        includes
+                            .defined
+                            .insert(name.to_string(), path.to_path_buf());
+                    }
+                }
+            } else if includes.stack.len() > 1 {
+                includes.errors.push(CompileError::Include {
+                    path: path.display().to_string(),
+                    reason: "an included file can only contain fun definitions".to_string(),
+                });
+                continue;
+            }
+            out.push(item);
+            continue;
+        };
+
+        let file = match include {
+            [Sexp::Atom(Atom::S(file))] => path.parent().unwrap_or(Path::new("")).join(file),
+            _ => {
+                includes.errors.push(CompileError::Include {
+                    path: item.to_string(),
+                    reason: "expected (include \"file.snek\")".to_string(),
+                });
+                continue;
+            }
+        };
+        let canonical = std::fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+        if let Some(start) = includes.stack.iter().position(|p| *p == canonical) {
+            let mut cycle: Vec<String> = includes.stack[start..]
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            cycle.push(canonical.display().to_string());
+            includes.errors.push(CompileError::IncludeCycle(cycle));
+            continue;
+        }
+        if !includes.done.insert(canonical.clone()) {
+            continue;
+        }
+        let text = match read_source(&file.to_string_lossy()) {
+            Ok(text) => text,
+            Err(e) => {
+                includes.errors.push(CompileError::Include {
+                    path: file.display().to_string(),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let included = match parse_program(&text) {
+            Ok(included) => included,
+            Err(error) => {
+                includes.errors.push(CompileError::InFile {
+                    file: file.display().to_string(),
+                    error: Box::new(error),
+                });
+                continue;
+            }
+        };
+        includes.stack.push(canonical);
+        out.extend(expand_includes(included, &file, includes));
+        includes.stack.pop();
+    }
+    out
+}
 
+/// Parses the program in `in_name` along with everything it includes,
+/// returning every error found if there are any.
+fn parse_with_includes(in_name: &str, in_contents: &str) -> Result<Sexp, Vec<CompileError>> {
+    let prog = parse_program(in_contents).map_err(|err| vec![err])?;
+    let path = Path::new(in_name);
+    let mut includes = Includes::default();
+    includes
+        .stack
+        .push(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    let items = expand_includes(prog, path, &mut includes);
+    if !includes.errors.is_empty() {
+        return Err(includes.errors);
+    }
+    Ok(Sexp::List(items))
+}
+
+/// Puts the prelude's definitions in front of the program, leaving out any
+/// the program defines itself.
+fn add_prelude(prog: Sexp) -> Sexp {
+    let Sexp::List(items) = prog else {
+        return prog;
+    };
+    let defined: HashSet<&str> = items.iter().filter_map(definition_name).collect();
+    let Ok(Sexp::List(prelude)) = parse_program(PRELUDE) else {
+        panic!("the prelude does not parse");
+    };
+    let mut out: Vec<Sexp> = prelude
+        .into_iter()
+        .filter(|item| definition_name(item).map_or(true, |name| !defined.contains(name)))
+        .collect();
+    out.extend(items);
+    Sexp::List(out)
+}
+
+fn read_source(in_name: &str) -> std::io::Result<String> {
+    let mut in_file = File::open(in_name)?;
+    let mut in_contents = String::new();
+    in_file.read_to_string(&mut in_contents)?;
+    Ok(in_contents)
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats a compile error in `in_name` as a JSON object, for
+/// `--error-format json`. There are no warnings yet, so the severity is
+/// always "error".
+fn error_json(err: &CompileError, in_name: &str) -> String {
+    let span = match err.span() {
+        Some((line, column)) => format!(
+            "{{\"file\": {}, \"line\": {line}, \"column\": {column}}}",
+            json_string(err.file().unwrap_or(in_name))
+        ),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"code\": \"{}\", \"severity\": \"error\", \"message\": {}, \"span\": {span}}}",
+        err.code(),
+        json_string(&err.to_string())
+    )
+}
+
+/// Reads and parses the program. If it doesn't parse, every error is
+/// reported, one per line, and the process exits with status 1.
+fn parse_source(in_name: &str, error_format: ErrorFormat) -> std::io::Result<Sexp> {
+    let in_contents = read_source(in_name)?;
+    match parse_with_includes(in_name, &in_contents).map(add_prelude) {
+        Ok(prog) => Ok(prog),
+        Err(errors) => {
+            for err in &errors {
+                match error_format {
+                    ErrorFormat::Human => eprintln!("{err}"),
+                    ErrorFormat::Json => eprintln!("{}", error_json(err, in_name)),
+                }
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn compile_program(_prog: &Sexp) -> String {
     // You will make result hold the result of actually compiling
     let result = "mov rax, 131";
 
@@ -22,9 +569,433 @@ our_code_starts_here:
 ",
         result
     );
+    asm_program
+}
+
+/// Runs one step of building the executable, reporting the tool's output if
+/// it fails.
+fn run_tool(command: &mut Command) -> std::io::Result<()> {
+    let output = command.output().map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("failed to run {:?}: {e}", command.get_program()),
+        )
+    })?;
+    if !output.status.success() {
+        let mut msg = format!("{:?} failed", command);
+        for stream in [&output.stdout, &output.stderr] {
+            if !stream.is_empty() {
+                msg.push('\n');
+                msg.push_str(String::from_utf8_lossy(stream).trim_end());
+            }
+        }
+        return Err(std::io::Error::other(msg));
+    }
+    Ok(())
+}
 
-    let mut out_file = File::create(out_name)?;
-    out_file.write_all(asm_program.as_bytes())?;
+/// Assembles and links the program against runtime/start.rs in `dir`, the way
+/// the Makefile's tests/%.run rule does, and returns the executable's path.
+fn build_executable(asm_program: &str, dir: &Path) -> std::io::Result<std::path::PathBuf> {
+    let format = if cfg!(target_os = "macos") {
+        "macho64"
+    } else {
+        "elf64"
+    };
+    let runtime = Path::new(env!("CARGO_MANIFEST_DIR")).join("runtime/start.rs");
+    let exe = dir.join("program.run");
 
+    std::fs::write(dir.join("our_code.s"), asm_program)?;
+    run_tool(
+        Command::new("nasm")
+            .args(["-f", format, "our_code.s", "-o", "our_code.o"])
+            .current_dir(dir),
+    )?;
+    run_tool(
+        Command::new("ar")
+            .args(["rcs", "libour_code.a", "our_code.o"])
+            .current_dir(dir),
+    )?;
+    run_tool(
+        Command::new("rustc")
+            .arg("-L")
+            .arg(dir)
+            .arg("-lour_code")
+            .arg(&runtime)
+            .arg("-o")
+            .arg(&exe),
+    )?;
+    Ok(exe)
+}
+
+/// Checks an error against what a NAME.err file expects: the error message
+/// must contain its text, ignoring case, and a line `exit code: N` in it
+/// also requires that exit code. Static errors have no exit code.
+fn check_expected_error(expected: &str, message: &str, code: Option<i32>) -> Result<(), String> {
+    let mut text = vec![];
+    for line in expected.lines() {
+        match line.strip_prefix("exit code:") {
+            Some(expected_code) => {
+                let expected_code = expected_code
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("bad exit code line `{line}`"))?;
+                if code != Some(expected_code) {
+                    return Err(format!(
+                        "expected exit code {expected_code}, got {code:?}: `{message}`"
+                    ));
+                }
+            }
+            None => text.push(line),
+        }
+    }
+    let text = text.join("\n");
+    if !message.to_lowercase().contains(&text.trim().to_lowercase()) {
+        return Err(format!(
+            "expected an error containing `{}`, got `{message}`",
+            text.trim()
+        ));
+    }
     Ok(())
 }
+
+/// Compiles and runs one program for `--test-dir`, building it in
+/// `build_dir`, and compares the result with its NAME.out or NAME.err. The
+/// program's input is read from NAME.args if there is one.
+fn run_dir_test(source: &Path, build_dir: &Path) -> Result<(), String> {
+    let read = |ext: &str| std::fs::read_to_string(source.with_extension(ext)).ok();
+    let in_name = source.to_string_lossy();
+    let in_contents = read_source(&in_name).map_err(|e| e.to_string())?;
+
+    // The program's output, or its error message and exit code.
+    let outcome = match parse_with_includes(&in_name, &in_contents).map(add_prelude) {
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            Err((errors.join("\n"), None))
+        }
+        Ok(prog) => {
+            let exe =
+                build_executable(&compile_program(&prog), build_dir).map_err(|e| e.to_string())?;
+            let output = Command::new(exe)
+                .args(read("args").unwrap_or_default().split_whitespace())
+                .output()
+                .map_err(|e| e.to_string())?;
+            if output.status.success() {
+                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            } else {
+                Err((
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                    output.status.code(),
+                ))
+            }
+        }
+    };
+
+    match (read("err"), outcome) {
+        (Some(expected), Err((message, code))) => check_expected_error(&expected, &message, code),
+        (Some(_), Ok(output)) => Err(format!("expected an error, got output `{output}`")),
+        (None, Err((message, _))) => Err(format!("expected output, got an error `{message}`")),
+        (None, Ok(output)) => {
+            let expected = read("out").unwrap_or_default();
+            if expected.trim().lines().eq(output.lines()) {
+                Ok(())
+            } else {
+                Err(format!("expected `{}`, got `{output}`", expected.trim()))
+            }
+        }
+    }
+}
+
+/// How many tests `--test-dir` ran that passed and failed.
+#[derive(Debug, Default, PartialEq)]
+struct TestSummary {
+    passed: usize,
+    failed: usize,
+}
+
+/// Runs every program in `dir` that has a NAME.out or NAME.err, in name
+/// order, writing a line for each test and then the summary to `out`.
+fn run_test_dir(dir: &Path, out: &mut impl Write) -> std::io::Result<TestSummary> {
+    let mut sources = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let expects = |ext| path.with_extension(ext).is_file();
+        if path.extension().is_some_and(|ext| ext == "snek") && (expects("out") || expects("err")) {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+
+    let build_dir = env::temp_dir().join(format!("snek-test-dir-{}", std::process::id()));
+    std::fs::create_dir_all(&build_dir)?;
+    let mut summary = TestSummary::default();
+    for source in &sources {
+        let name = source.file_stem().unwrap_or_default().to_string_lossy();
+        match run_dir_test(source, &build_dir) {
+            Ok(()) => {
+                summary.passed += 1;
+                writeln!(out, "PASS {name}")?;
+            }
+            Err(reason) => {
+                summary.failed += 1;
+                writeln!(out, "FAIL {name}: {reason}")?;
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&build_dir);
+    writeln!(out, "{} passed, {} failed", summary.passed, summary.failed)?;
+    Ok(summary)
+}
+
+fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let error_format = if cli.json_errors {
+        ErrorFormat::Json
+    } else {
+        cli.error_format
+    };
+
+    // `check input.snek` runs the front end and reports errors without
+    // generating any assembly.
+    if let Some(Cmd::Check { source }) = &cli.command {
+        parse_source(source, error_format)?;
+        return Ok(());
+    }
+
+    // `--test-dir DIR` runs the tests in DIR, exiting with status 1 if any
+    // of them failed.
+    if let Some(dir) = &cli.test_dir {
+        let summary = run_test_dir(Path::new(dir), &mut std::io::stdout())?;
+        if summary.failed > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let source = cli.source.as_deref().expect("clap requires a source file");
+    let prog = parse_source(source, error_format)?;
+
+    // `--run` assembles and links the program in a temporary directory, and
+    // runs it. The program's output goes straight to the terminal and its
+    // exit status is passed on.
+    if cli.run {
+        let dir = env::temp_dir().join(format!("snek-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let status = build_executable(&compile_program(&prog), &dir).and_then(|exe| {
+            let mut program = Command::new(exe);
+            program.args(&cli.input);
+            program.status()
+        });
+        let _ = std::fs::remove_dir_all(&dir);
+        match status {
+            Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let text = match cli.emit {
+        Emit::Ast => format!("{prog}\n"),
+        Emit::Asm => compile_program(&prog),
+    };
+    match cli.output.as_ref().or(cli.legacy_output.as_ref()) {
+        Some(out_name) => File::create(out_name)?.write_all(text.as_bytes())?,
+        None => std::io::stdout().write_all(text.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_leading_comment() {
+        let text = "; computes 1 + 2\n(+ 1 2)";
+        assert_eq!(strip_comments(text).unwrap(), "\n(+ 1 2)");
+    }
+
+    #[test]
+    fn test_strip_trailing_comment() {
+        let text = "(add1 5) ; should be 6\n";
+        assert_eq!(strip_comments(text).unwrap(), "(add1 5) \n");
+    }
+
+    #[test]
+    fn test_keep_semicolon_in_string() {
+        let text = "(print \"a;b \\\" ;c\") ; gone";
+        assert_eq!(strip_comments(text).unwrap(), "(print \"a;b \\\" ;c\") ");
+    }
+
+    #[test]
+    fn test_strip_block_comments() {
+        let text = "(+ 1 #| two\n#| nested |# |# 2) ; done";
+        assert_eq!(
+            strip_comments(text).unwrap(),
+            "(+ 1       \n                2) "
+        );
+        // Markers inside strings and line comments don't start a block comment.
+        let text = "(print \"#|\") ; #|\n5";
+        assert_eq!(strip_comments(text).unwrap(), "(print \"#|\") \n5");
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let err = parse_program("(+ 1 2)\n  #| #| |#\n").unwrap_err();
+        assert_eq!(
+            err,
+            CompileError::UnterminatedComment { line: 2, column: 3 }
+        );
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let prog = parse_program("(+ -5 0x1F) 1_000 -0x10 x_1").unwrap();
+        assert_eq!(prog, parse("((+ -5 31) 1000 -16 x_1)").unwrap());
+        assert_eq!(
+            parse_program("0x8000_0000_0000_0000").unwrap_err(),
+            CompileError::NumberOutOfRange("0x8000_0000_0000_0000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_only_comments_is_empty_program() {
+        let text = "; nothing here\n;; or here\n";
+        assert_eq!(
+            parse_program(text).unwrap_err().to_string(),
+            "Invalid: empty program"
+        );
+    }
+
+    #[test]
+    fn test_cli_legacy_form() {
+        let cli = Cli::try_parse_from(["diamondback", "in.snek", "out.s"]).unwrap();
+        assert_eq!(cli.source.as_deref(), Some("in.snek"));
+        assert_eq!(cli.legacy_output.as_deref(), Some("out.s"));
+        assert_eq!(cli.emit, Emit::Asm);
+    }
+
+    #[test]
+    fn test_cli_options() {
+        let cli =
+            Cli::try_parse_from(["diamondback", "in.snek", "-o", "out", "--emit", "ast"]).unwrap();
+        assert_eq!(cli.output.as_deref(), Some("out"));
+        assert_eq!(cli.emit, Emit::Ast);
+        let cli =
+            Cli::try_parse_from(["diamondback", "check", "in.snek", "--json-errors"]).unwrap();
+        assert!(matches!(cli.command, Some(Cmd::Check { .. })));
+        assert!(cli.json_errors);
+        // --input only makes sense for --run, which has no output file.
+        assert!(Cli::try_parse_from(["diamondback", "in.snek", "--input", "5"]).is_err());
+        assert!(Cli::try_parse_from(["diamondback", "in.snek", "out.s", "--run"]).is_err());
+        let cli = Cli::try_parse_from(["diamondback", "--test-dir", "tests/runner"]).unwrap();
+        assert_eq!(cli.test_dir.as_deref(), Some("tests/runner"));
+        assert!(Cli::try_parse_from(["diamondback", "in.snek", "--test-dir", "tests"]).is_err());
+    }
+
+    #[test]
+    fn test_check_expected_error() {
+        assert!(check_expected_error("Overflow\n", "an error occurred: overflow", Some(1)).is_ok());
+        assert!(check_expected_error("overflow\nexit code: 1", "overflow", Some(1)).is_ok());
+        assert!(check_expected_error("overflow\nexit code: 2", "overflow", Some(1)).is_err());
+        assert!(check_expected_error("overflow", "invalid argument", Some(2)).is_err());
+        // Static errors have no exit code.
+        assert!(check_expected_error("empty program", "Invalid: empty program", None).is_ok());
+        assert!(check_expected_error("exit code: 1", "Invalid: empty program", None).is_err());
+        assert!(check_expected_error("exit code: one", "overflow", Some(1)).is_err());
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a \"b\"\n\\"), "\"a \\\"b\\\"\\n\\\\\"");
+    }
+
+    #[test]
+    fn test_parse_error_json() {
+        let err = parse_program("(add1 5)\n(+ 1").unwrap_err();
+        assert_eq!(err.span(), Some((2, 5)));
+        assert_eq!(
+            error_json(&err, "bad.snek"),
+            "{\"code\": \"E0002\", \"severity\": \"error\", \
+             \"message\": \"Invalid: parse error 2:5: unexpected eof\", \
+             \"span\": {\"file\": \"bad.snek\", \"line\": 2, \"column\": 5}}"
+        );
+        let err = parse_program("; nothing\n").unwrap_err();
+        assert!(error_json(&err, "empty.snek").ends_with("\"span\": null}"));
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = env::temp_dir().join(format!("snek_include_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+        let parse_main = |text: &str| {
+            let main = dir.join("main.snek");
+            std::fs::write(&main, text).unwrap();
+            parse_with_includes(&main.to_string_lossy(), text)
+        };
+        write(
+            "lib/math.snek",
+            "(fun (double x) (+ x x))\n(include \"abs.snek\")",
+        );
+        write("lib/abs.snek", "(fun (abs x) (if (< x 0) (- 0 x) x))");
+
+        // Includes are spliced in place, relative to the including file.
+        let prog = parse_main("(include \"lib/math.snek\")\n(double input)").unwrap();
+        let expected =
+            "((fun (double x) (+ x x)) (fun (abs x) (if (< x 0) (- 0 x) x)) (double input))";
+        assert_eq!(prog, parse(expected).unwrap());
+
+        // A file included twice only adds its definitions once.
+        let prog =
+            parse_main("(include \"lib/abs.snek\")\n(include \"lib/math.snek\")\n0").unwrap();
+        assert_eq!(prog.to_string().matches("(fun (abs x)").count(), 1);
+
+        let errors = parse_main("(fun (abs x) x)\n(include \"lib/math.snek\")\n0").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "E0007");
+
+        write("lib/abs.snek", "(include \"math.snek\")");
+        let errors = parse_main("(include \"lib/math.snek\")\n0").unwrap_err();
+        assert_eq!(errors[0].code(), "E0006");
+
+        // Errors in an included file point into that file.
+        write("lib/abs.snek", "(fun (abs x)\n  (if x))) x)");
+        let errors = parse_main("(include \"lib/math.snek\")\n0").unwrap_err();
+        assert_eq!(errors[0].code(), "E0002");
+        assert_eq!(errors[0].span(), Some((2, 12)));
+        assert!(errors[0].file().unwrap().ends_with("abs.snek"));
+
+        write("lib/abs.snek", "(abs 5)");
+        let errors = parse_main("(include \"lib/math.snek\")\n0").unwrap_err();
+        assert_eq!(errors[0].code(), "E0005");
+
+        // Every error is reported, not just the first.
+        let errors =
+            parse_main("(include \"lib/one.snek\")\n(include 5)\n(include \"lib/two.snek\")\n0")
+                .unwrap_err();
+        let codes: Vec<_> = errors.iter().map(CompileError::code).collect();
+        assert_eq!(codes, ["E0005", "E0005", "E0005"]);
+        assert!(errors[0].to_string().contains("one.snek"));
+        assert!(errors[2].to_string().contains("two.snek"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_prelude() {
+        let prog = add_prelude(parse_program("(fun (abs x) x)\n(max (abs -1) 2)").unwrap());
+        let Sexp::List(items) = prog else {
+            panic!("expected a list");
+        };
+        let names: Vec<_> = items.iter().map(definition_name).collect();
+        assert_eq!(
+            names,
+            [Some("max"), Some("min"), Some("abs"), None],
+            "the program's own abs replaces the prelude's"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// `cargo study <command>` (or `cargo run -p study-agent -- <command>`) runs the study agent on
+// demand for the project in the current directory.
+use std::path::{Path, PathBuf};
+
+static USAGE: &str = "usage: cargo study <init|snapshot|flush|status|export [--csv] [<changelog repo>]>";
+
+// The nearest directory, starting from the current one, with a config.txt, or else with a
+// Cargo.toml.
+fn project_dir() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let find = |name: &str| cwd.ancestors().find(|dir| dir.join(name).is_file()).map(Path::to_path_buf);
+    find("config.txt").or_else(|| find("Cargo.toml"))
+}
+
+fn main() {
+    // cargo runs `cargo-study study <command>` for `cargo study <command>`.
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "study") {
+        args.remove(0);
+    }
+    let manifest_dir = match project_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("cargo-study: not inside a cargo project");
+            std::process::exit(2);
+        }
+    };
+
+    let result = match args.first().map(String::as_str) {
+        Some("init") => study_agent::init(&manifest_dir).map(|()| "the changelog is ready".to_owned()),
+        Some("snapshot") => study_agent::snapshot(&manifest_dir).map(|()| "recorded a snapshot".to_owned()),
+        Some("flush") => {
+            study_agent::flush(&manifest_dir);
+            Ok("pushed pending snapshots (see the log for any errors)".to_owned())
+        }
+        Some("status") => study_agent::status(&manifest_dir),
+        Some("export") => {
+            let csv = args.iter().any(|arg| arg == "--csv");
+            let format = if csv { study_agent::ExportFormat::Csv } else { study_agent::ExportFormat::Jsonl };
+            let changelog = args.iter().skip(1).find(|arg| *arg != "--csv").map_or_else(|| manifest_dir.join("changelog"), PathBuf::from);
+            if let Err(e) = study_agent::export(&changelog, format, &mut std::io::stdout().lock()) {
+                eprintln!("cargo-study: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    match result {
+        Ok(message) => println!("{}", message),
+        Err(e) => {
+            eprintln!("cargo-study: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+// Committing snapshots to the changelog, milestone tags, and keeping its history small.
+
+use std::fs;
+use std::process::Command;
+use std::path::{Path, PathBuf};
+
+use git2::{IndexAddOption, Repository, ResetType, Signature};
+
+use crate::{LOCK_WAIT, lock_changelog, unix_now};
+use crate::config::Config;
+use crate::copy::GENERATED_FILES;
+use crate::logger::{Logger, format_timestamp};
+use crate::push::Target;
+
+// Creates an annotated tag `name` on the latest changelog commit, unless a tag of that name
+// already exists. Returns whether a tag was created.
+pub(crate) fn tag_milestone(logger: &mut Logger, changelog_path: &Path, name: &str) -> bool {
+    match try_tag_milestone(changelog_path, name) {
+        Ok(true) => {
+            logger.info(&format!("tagged the latest snapshot as {}", name));
+            true
+        }
+        Ok(false) => {
+            logger.debug(&format!("tag {} already exists", name));
+            false
+        }
+        Err(e) => {
+            logger.error(&format!("failed to create tag {}: {}", name, e));
+            false
+        }
+    }
+}
+
+fn try_tag_milestone(changelog_path: &Path, name: &str) -> Result<bool, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    if repo.find_reference(&format!("refs/tags/{}", name)).is_ok() {
+        return Ok(false);
+    }
+    let head = repo.head()?.peel(git2::ObjectType::Commit)?;
+    let message = format!("milestone {} at {}", name, format_timestamp(unix_now()));
+    repo.tag(name, &head, &changelog_signature(&repo)?, &message, false)?;
+    Ok(true)
+}
+
+// Seconds since the latest changelog commit, or None if there are no commits yet.
+pub(crate) fn seconds_since_last_commit(changelog_path: &Path) -> Option<u64> {
+    let repo = Repository::open(changelog_path).ok()?;
+    let time = repo.head().ok()?.peel_to_commit().ok()?.time().seconds();
+    Some(unix_now().saturating_sub(time.max(0) as u64))
+}
+
+// How often the changelog is garbage collected, and where the time of the last collection is
+// kept (in .git, so it is never committed).
+static GC_INTERVAL_SECS: u64 = 7 * 24 * 60 * 60;
+
+fn last_gc_path(changelog_path: &Path) -> PathBuf {
+    changelog_path.join(".git").join("last_gc")
+}
+
+// Runs `git gc` once GC_INTERVAL_SECS have passed since the last time, or right away if .git is
+// bigger than `max_size_mb`, in which case already-pushed history is squashed first so that gc can
+// drop it. Commits that milestone tags point to are kept either way.
+pub(crate) fn compact_changelog(logger: &mut Logger, config: &Config, changelog_path: &Path) {
+    let git_dir = changelog_path.join(".git");
+    let max_bytes = config.max_size_mb.map(|mb| mb.saturating_mul(1024 * 1024));
+    let over_cap = max_bytes.is_some_and(|max| dir_size(&git_dir) > max);
+    let last_gc = fs::read_to_string(last_gc_path(changelog_path)).ok().and_then(|time| time.trim().parse::<u64>().ok());
+    if !over_cap && last_gc.is_some_and(|time| unix_now().saturating_sub(time) < GC_INTERVAL_SECS) {
+        return;
+    }
+
+    let _lock = match lock_changelog(logger, changelog_path, LOCK_WAIT) {
+        Some(lock) => lock,
+        None => return,
+    };
+    if over_cap {
+        logger.info(&format!("changelog is larger than {} MB; squashing pushed history", config.max_size_mb.unwrap_or(0)));
+        squash_history(logger, changelog_path, &config.targets(), 0, config.squash_keep);
+    }
+    run_git_gc(logger, changelog_path);
+    if let Err(e) = fs::write(last_gc_path(changelog_path), format!("{}\n", unix_now())) {
+        logger.warn(&format!("failed to record the time of the last gc: {}", e));
+    }
+    if let Some(max) = max_bytes {
+        let size = dir_size(&git_dir);
+        if size > max {
+            logger.warn(&format!("changelog is still {} MB after compacting; history that hasn't been pushed can't be squashed", size / (1024 * 1024)));
+        }
+    }
+}
+
+// libgit2 can't repack or prune, so this needs a git executable; without one it does nothing.
+// Reflogs are expired along the way, as they would otherwise keep squashed history alive.
+fn run_git_gc(logger: &mut Logger, changelog_path: &Path) -> bool {
+    let output = Command::new("git").current_dir(changelog_path)
+                                     .args(["-c", "gc.reflogExpire=now", "-c", "gc.reflogExpireUnreachable=now"])
+                                     .args(["gc", "--aggressive", "--prune=now", "--quiet"])
+                                     .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            logger.info("garbage collected the changelog");
+            true
+        }
+        Ok(output) => {
+            logger.warn(&format!("git gc failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+            false
+        }
+        Err(e) => {
+            logger.info(&format!("not garbage collecting the changelog: can't run git: {}", e));
+            false
+        }
+    }
+}
+
+// Total size of the files under `path`.
+fn dir_size(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries.flatten()
+           .map(|entry| match entry.file_type() {
+               Ok(t) if t.is_dir() => dir_size(&entry.path()),
+               Ok(_) => entry.metadata().map_or(0, |metadata| metadata.len()),
+               Err(_) => 0,
+           })
+           .sum()
+}
+
+// Placeholders that can appear in the `commit_message` template of config.txt.
+pub(crate) static COMMIT_PLACEHOLDERS: &[&str] = &["{kind}", "{timestamp}", "{profile}", "{added}", "{modified}", "{deleted}"];
+static DEFAULT_COMMIT_MESSAGE: &str = "{kind} {timestamp} ({profile}): {added} added, {modified} modified, {deleted} deleted";
+
+// Commits the changelog. `kind` says what triggered the commit (a snapshot or one of the reports
+// added on top of it), and `template` is the commit message template from config.txt, if any.
+pub(crate) fn commit_to_git(logger: &mut Logger, changelog_path: &Path, kind: &str, template: Option<&str>) {
+    if let Err(e) = try_commit_to_git(changelog_path, kind, template.unwrap_or(DEFAULT_COMMIT_MESSAGE)) {
+        logger.error(&format!("failed to commit files to git: {}", e));
+    }
+}
+
+// The identity used for changelog commits: the user's git identity if they have one configured,
+// otherwise a fixed placeholder so that committing never depends on git being set up.
+fn changelog_signature(repo: &Repository) -> Result<Signature<'static>, git2::Error> {
+    repo.signature().or_else(|_| Signature::now("changelog", "changelog@localhost"))
+}
+
+// Number of files added, modified and deleted by a commit.
+struct ChangeCounts {
+    added: usize,
+    modified: usize,
+    deleted: usize,
+}
+
+fn format_commit_message(template: &str, kind: &str, timestamp: &str, profile: &str, counts: &ChangeCounts) -> String {
+    template.replace("{kind}", kind)
+            .replace("{timestamp}", timestamp)
+            .replace("{profile}", profile)
+            .replace("{added}", &counts.added.to_string())
+            .replace("{modified}", &counts.modified.to_string())
+            .replace("{deleted}", &counts.deleted.to_string())
+}
+
+// Stages the whole changelog tree, including dotfiles and deletions, and commits it. Nothing is
+// committed if the tree is unchanged since the last commit.
+fn try_commit_to_git(changelog_path: &Path, kind: &str, template: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    // The project's .gitignore is copied along with everything else, and usually lists Cargo.lock
+    // or the test assembly, so generated files are added even if it ignores them.
+    index.add_all(GENERATED_FILES.iter(), IndexAddOption::FORCE, None)?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None, // no commits yet
+    };
+    if let Some(parent) = &parent {
+        if parent.tree_id() == tree.id() {
+            return Ok(());
+        }
+    }
+
+    let parent_tree = match &parent {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let mut counts = ChangeCounts { added: 0, modified: 0, deleted: 0 };
+    let mut sources_changed = false;
+    for delta in repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?.deltas() {
+        match delta.status() {
+            git2::Delta::Added => counts.added += 1,
+            git2::Delta::Deleted => counts.deleted += 1,
+            _ => counts.modified += 1,
+        }
+        let path = delta.new_file().path().or(delta.old_file().path());
+        sources_changed |= !path.is_some_and(|path| GENERATED_FILES.iter().any(|f| path == Path::new(f)));
+    }
+    // A snapshot where only metadata changed (say, `cargo check` after `cargo build`) would be a
+    // near-duplicate of the last one. Its files stay staged and go into the next commit.
+    if kind == "snapshot" && parent.is_some() && !sources_changed {
+        return Ok(());
+    }
+    // Only build scripts are told the profile; the rustc wrapper and test runner are not.
+    let profile = std::env::var("PROFILE").unwrap_or_else(|_| "unknown".to_owned());
+    let message = format_commit_message(template, kind, &format_timestamp(unix_now()), &profile, &counts);
+
+    let sig = changelog_signature(&repo)?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &parents)?;
+    Ok(())
+} 
+
+// Collapses old history into a single root commit once there are more than `squash_after`
+// commits, keeping the `keep` most recent commits on top of it. Only commits that are already
+// on every one of `targets` are folded in, so nothing that hasn't been pushed yet is ever lost,
+// not even to a mirror that has been down for a while.
+// Returns true if history was rewritten (and so the next push will be forced).
+pub(crate) fn squash_history(logger: &mut Logger, changelog_path: &Path, targets: &[&Target], squash_after: usize, keep: usize) -> bool {
+    match try_squash_history(changelog_path, targets, squash_after, keep) {
+        Ok(Some(count)) => {
+            logger.info(&format!("squashed {} commits into {}", count, keep + 1));
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            logger.info(&format!("not squashing: {}", e));
+            false
+        }
+    }
+}
+
+fn try_squash_history(changelog_path: &Path, targets: &[&Target], squash_after: usize, keep: usize) -> Result<Option<usize>, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let head = repo.head()?.peel_to_commit()?;
+
+    let mut walk = repo.revwalk()?;
+    walk.push(head.id())?;
+    let count = walk.count();
+    if count <= squash_after || count <= keep + 1 {
+        return Ok(None);
+    }
+
+    // Everything up to and including `base` gets squashed into one commit.
+    let base = repo.revparse_single(&format!("HEAD~{}", keep))?.peel_to_commit()?;
+    for target in targets {
+        let pushed = repo.refname_to_id(&target.tracking_ref())
+                         .map_err(|_| git2::Error::from_str(&format!("nothing has been pushed to {} yet", target.remote)))?;
+        if pushed != base.id() && !repo.graph_descendant_of(pushed, base.id())? {
+            return Err(git2::Error::from_str(&format!("commits to squash have not been pushed to {} yet", target.remote)));
+        }
+    }
+
+    let sig = changelog_signature(&repo)?;
+    let new_root = repo.commit(None, &sig, &sig, "changelog squash", &base.tree()?, &[])?;
+    let mut new_head = repo.find_commit(new_root)?;
+
+    // Every changelog commit is a full snapshot, so the kept commits can be recreated from their
+    // trees alone.
+    let mut kept = repo.revwalk()?;
+    kept.push(head.id())?;
+    kept.hide(base.id())?;
+    kept.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    for oid in kept {
+        let commit = repo.find_commit(oid?)?;
+        let message = commit.message().unwrap_or("changelog update");
+        let oid = repo.commit(None, &commit.author(), &commit.committer(), message, &commit.tree()?, &[&new_head])?;
+        new_head = repo.find_commit(oid)?;
+    }
+
+    repo.reset(new_head.as_object(), ResetType::Soft, None)?;
+    Ok(Some(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use crate::DEFAULT_BRANCH;
+    use crate::config::parse_config;
+    use crate::push::{Auth, git_push, sync_remote, try_git_push};
+    use crate::test_util::*;
+
+    #[test]
+    fn test_squash_history() {
+        let root = TempDir::new("squash_history");
+        let changelog = root.join("changelog");
+        let remote = root.join("remote.git");
+        fs::create_dir_all(&changelog).unwrap();
+        fs::create_dir_all(&remote).unwrap();
+        let mut logger = Logger::none();
+
+        let remote_repo = Repository::init_bare(&remote).unwrap();
+        let repo = init_test_repo(&changelog, remote.to_str().unwrap());
+
+        for i in 0..5 {
+            fs::write(changelog.join("main.rs"), format!("version {}", i)).unwrap();
+            commit_to_git(&mut logger, &changelog, "snapshot", None);
+        }
+        let auth = Auth::UserPass { username: "592089".to_owned(), password: String::new() };
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+        // This one has not been pushed and must survive the squash untouched.
+        fs::write(changelog.join("main.rs"), "version 5").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        // Not above the threshold yet.
+        assert!(!squash_history(&mut logger, &changelog, &[&test_target()], 6, 1));
+
+        // A mirror that hasn't caught up holds the squash back.
+        let mirror_remote = root.join("mirror.git");
+        Repository::init_bare(&mirror_remote).unwrap();
+        let mirror = Target { remote: "mirror1".to_owned(), branch: DEFAULT_BRANCH.to_owned() };
+        sync_remote(&changelog, &mirror, mirror_remote.to_str().unwrap()).unwrap();
+        assert!(!squash_history(&mut logger, &changelog, &[&test_target(), &mirror], 3, 1));
+        git_push(&mut logger, &changelog, &mirror, &auth);
+
+        assert!(squash_history(&mut logger, &changelog, &[&test_target(), &mirror], 3, 1));
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+        assert_eq!(fs::read_to_string(changelog.join("main.rs")).unwrap(), "version 5");
+        let squashed = repo.revparse_single("HEAD~1:main.rs").unwrap().peel_to_blob().unwrap();
+        assert_eq!(squashed.content(), b"version 4");
+
+        // Keeping the unpushed commit out of the squash is required.
+        assert!(!squash_history(&mut logger, &changelog, &[&test_target()], 1, 0));
+
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+        assert_eq!(commit_count(&remote_repo, "refs/heads/main"), 2);
+    }
+
+    #[test]
+    fn test_commit_stages_additions_and_deletions() {
+        let root = TempDir::new("commit_to_git");
+        let changelog = root.join("changelog");
+        fs::create_dir_all(changelog.join("src")).unwrap();
+        let mut logger = Logger::none();
+
+        let repo = init_test_repo(&changelog, "https://example.com/p1.git");
+
+        fs::write(changelog.join("src").join("main.rs"), "fn main() {}").unwrap();
+        fs::write(changelog.join("old.snek"), "5").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        fs::remove_file(changelog.join("old.snek")).unwrap();
+        fs::write(changelog.join(".gitignore"), "target/").unwrap();
+        fs::write(changelog.join("new.snek"), "6").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        assert_eq!(head_files(&repo), vec![".gitignore", "new.snek", "src/main.rs"]);
+        assert!(repo.statuses(None).unwrap().is_empty());
+
+        // Nothing changed, so there is nothing to commit.
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+    }
+
+    #[test]
+    fn test_commit_messages() {
+        let changelog = TempDir::new("study_messages");
+        let repo = init_test_repo(&changelog, "https://example.com/a.git");
+        let mut logger = Logger::none();
+
+        fs::write(changelog.join("a.rs"), "1").unwrap();
+        fs::write(changelog.join("b.rs"), "2").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        let message = repo.head().unwrap().peel_to_commit().unwrap().message().unwrap().to_owned();
+        assert!(message.starts_with("snapshot 20"));
+        assert!(message.ends_with(": 2 added, 0 modified, 0 deleted"));
+
+        fs::write(changelog.join("a.rs"), "3").unwrap();
+        fs::remove_file(changelog.join("b.rs")).unwrap();
+        fs::write(changelog.join("c.rs"), "4").unwrap();
+        commit_to_git(&mut logger, &changelog, "tests", Some("[{kind}] +{added} ~{modified} -{deleted}"));
+        let message = repo.head().unwrap().peel_to_commit().unwrap().message().unwrap().to_owned();
+        assert_eq!(message, "[tests] +1 ~1 -1");
+    }
+
+    #[test]
+    fn test_snapshot_skips_metadata_only_changes() {
+        let changelog = TempDir::new("study_metadata_only");
+        let repo = init_test_repo(&changelog, "https://example.com/a.git");
+        let mut logger = Logger::none();
+
+        fs::write(changelog.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(changelog.join("build_meta.json"), "{\"command\": \"cargo build\"}").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert!(seconds_since_last_commit(&changelog).unwrap() < 5);
+
+        fs::write(changelog.join("build_meta.json"), "{\"command\": \"cargo check\"}").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert_eq!(commit_count(&repo, "HEAD"), 1);
+
+        fs::write(changelog.join("main.rs"), "fn main() { }").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+    }
+
+    #[test]
+    fn test_milestone_tag() {
+        let root = TempDir::new("study_tag");
+        let server = root.join("server.git");
+        Repository::init_bare(&server).unwrap();
+        let changelog = root.join("changelog");
+        let repo = init_test_repo(&changelog, server.to_str().unwrap());
+        let mut logger = Logger::none();
+        let auth = Auth::UserPass { username: "1".to_owned(), password: String::new() };
+
+        fs::write(changelog.join("main.rs"), "fn main() {}").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        let submitted = repo.head().unwrap().target().unwrap();
+        assert!(tag_milestone(&mut logger, &changelog, "submission1"));
+
+        // Leaving the setting in place doesn't move the tag to later snapshots.
+        fs::write(changelog.join("main.rs"), "fn main() { }").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert!(!tag_milestone(&mut logger, &changelog, "submission1"));
+        let tag = repo.find_reference("refs/tags/submission1").unwrap().peel_to_tag().unwrap();
+        assert_eq!(tag.target_id(), submitted);
+
+        try_git_push(&changelog, &test_target(), &auth).unwrap();
+        let pushed = Repository::open_bare(&server).unwrap();
+        assert_eq!(pushed.find_reference("refs/tags/submission1").unwrap().peel_to_commit().unwrap().id(), submitted);
+        try_git_push(&changelog, &test_target(), &auth).unwrap();
+
+        let config = parse_config(&mut logger, "participant_id: 1, project: p1, tag: submission1").unwrap();
+        assert_eq!(config.tag.as_deref(), Some("submission1"));
+        assert!(parse_config(&mut logger, "participant_id: 1, project: p1, tag: 'final submission'").is_none());
+    }
+
+    #[test]
+    fn test_compact_changelog() {
+        let root = TempDir::new("study_compact");
+        let server = root.join("server.git");
+        Repository::init_bare(&server).unwrap();
+        let changelog = root.join("changelog");
+        let repo = init_test_repo(&changelog, server.to_str().unwrap());
+        let mut logger = Logger::none();
+        let auth = Auth::UserPass { username: "1".to_owned(), password: String::new() };
+
+        for i in 0..4 {
+            fs::write(changelog.join("main.rs"), format!("version {}", i)).unwrap();
+            commit_to_git(&mut logger, &changelog, "snapshot", None);
+        }
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+
+        // Under the cap, only the first call garbage collects.
+        let mut config = test_config();
+        config.max_size_mb = Some(1024);
+        compact_changelog(&mut logger, &config, &changelog);
+        assert!(last_gc_path(&changelog).exists());
+        assert_eq!(commit_count(&repo, "HEAD"), 4);
+
+        // Over the cap, pushed history is squashed right away.
+        config.max_size_mb = Some(0);
+        compact_changelog(&mut logger, &config, &changelog);
+        assert_eq!(commit_count(&repo, "HEAD"), 1);
+        assert_eq!(fs::read_to_string(changelog.join("main.rs")).unwrap(), "version 3");
+
+        assert!(parse_config(&mut logger, "participant_id: 1, project: p1, max_size_mb: lots").is_none());
+    }
+}
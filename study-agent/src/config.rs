@@ -0,0 +1,627 @@
+// Reading config.txt, and the template left behind for it.
+
+use std::fs;
+use std::path::Path;
+use std::io::{Read, Write};
+
+use regex::Regex;
+
+use crate::{BRANCH_VAR, DEFAULT_BRANCH, DEFAULT_REMOTE, REMOTE_VAR, SERVER, SERVER_VAR, TAG_VAR};
+use crate::commit::COMMIT_PLACEHOLDERS;
+use crate::logger::Logger;
+use crate::push::Target;
+
+pub(crate) static CONFIG_TEMPLATE: &str = "\
+# Copy this file to config.txt and fill in your details to enable changelog recording.
+# The format is a subset of TOML: `key = value` lines with quoted strings, integers, true or false,
+# and for `mirrors` a list of strings on one line. Tables and multi-line values aren't supported.
+participant_id = \"<your participant id>\"
+git_password = \"<your git password>\"
+project = \"<your project name>\"
+# Instead of git_password you can use an SSH deploy key with `ssh_key = \"<path to private key>\"`,
+# or put a personal access token in the STUDY_GIT_TOKEN environment variable.
+# The build log goes to log.txt in the temp directory; set `log_path = \"<file>\"` or STUDY_LOG to move it.
+# `server`, `remote` and `branch` default to git.goto.ucsd.edu, origin and main.
+# Common secrets and email addresses are redacted from snapshots. To redact more, set
+# `redact = '<regular expression>'` (use `|` to combine several patterns).
+# To also record compiler errors and warnings, build with RUSTC_WRAPPER=changelog/.git/study-rustc
+# (as an absolute path) once a first build has created it. To record which tests pass, run
+# `changelog/.git/study-rustc --study-test` in place of `cargo test`.
+# Commit messages can be changed with `commit_message = \"<template>\"`, using {kind}, {timestamp},
+# {profile}, {added}, {modified} and {deleted}.
+# To take at most one snapshot every N seconds, set `snapshot_interval_secs = N`.
+# To keep compiled artifacts (*.s, *.o, *.run, ...) out of snapshots, set `skip_artifacts = true`.
+# To record the assembly and link errors of your tests in artifacts/, set `capture_artifacts = true`.
+# To cap the size of the local changelog history, set `max_size_mb = N`.
+# If your study requires encrypted snapshots, set `encrypt_key = \"<age public key, age1...>\"`.
+# To also push to backup remotes, list their URLs with `mirrors = [\"<url>\", \"<url>\"]`. They are pushed to
+# with your own SSH agent or git credential helper, never with the study password or token.
+# To mark the next snapshot as a milestone, such as a submission, set `tag = \"<name>\"` or STUDY_TAG.
+# Set `enabled = false` at any time to stop collection; nothing is copied, committed or pushed then.
+";
+
+// Tells the participant with a cargo warning whether snapshots are being collected. The message
+// last shown is kept at `marker`, so it is only repeated when collection is switched on or off (or
+// after `cargo clean`). Returns whether the warning was shown.
+pub(crate) fn announce_collection(logger: &mut Logger, marker: &Path, enabled: bool) -> bool {
+    let message = if enabled {
+        "study snapshot collection is active for this project (set `enabled = false` in config.txt to stop it)"
+    }
+    else {
+        "study snapshot collection is disabled in config.txt; nothing is being recorded"
+    };
+    if fs::read_to_string(marker).is_ok_and(|shown| shown == message) {
+        return false;
+    }
+    println!("cargo:warning={}", message);
+    if let Err(e) = fs::write(marker, message) {
+        logger.warn(&format!("failed to remember the collection notice: {}", e));
+    }
+    true
+}
+
+// Writes config.txt.example into the manifest directory, unless one is already there.
+// The real config.txt is never created automatically.
+pub(crate) fn write_config_template(logger: &mut Logger, manifest_path: &Path) {
+    let example_path = manifest_path.join("config.txt.example");
+    let example_file = fs::OpenOptions::new()
+                                            .write(true)
+                                            .create_new(true)
+                                            .open(&example_path);
+    match example_file {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(CONFIG_TEMPLATE.as_bytes()) {
+                logger.error(&format!("failed to write config.txt.example: {}", e));
+                return;
+            }
+            logger.info("wrote config.txt.example");
+            println!("cargo:warning=changelog recording is disabled: no config.txt found (see config.txt.example)");
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            // Don't overwrite an existing template.
+        }
+        Err(e) => logger.error(&format!("failed to create config.txt.example: {}", e)),
+    }
+}
+
+pub(crate) struct Config {
+    pub(crate) participant_id: String,
+    // Only used when there is no SSH key or access token.
+    pub(crate) git_password: Option<String>,
+    // Private key used to push over SSH instead of HTTPS.
+    pub(crate) ssh_key: Option<String>,
+    pub(crate) project: String,
+    // Host the changelog is pushed to, SERVER unless configured otherwise.
+    pub(crate) server: String,
+    // Remote and branch the changelog is committed and pushed to, DEFAULT_REMOTE and
+    // DEFAULT_BRANCH unless configured otherwise.
+    pub(crate) target: Target,
+    // Whether the participant consents to collection. It is withdrawn with `enabled = false`,
+    // `consent = "no"` or `opt_out = true`, and then nothing is copied, committed or pushed.
+    pub(crate) enabled: bool,
+    // Once the changelog has more than this many commits, already-pushed history is squashed.
+    pub(crate) squash_after: Option<usize>,
+    // Number of recent commits left untouched by a squash.
+    pub(crate) squash_keep: usize,
+    // Once .git is bigger than this, pushed history is squashed and garbage collected.
+    pub(crate) max_size_mb: Option<u64>,
+    // Whether to leave files matching ARTIFACT_PATTERNS out of snapshots.
+    pub(crate) skip_artifacts: bool,
+    // Whether to record generated assembly and link errors from tests/ in artifacts/.
+    pub(crate) capture_artifacts: bool,
+    // age public key that snapshots and reports are encrypted for.
+    pub(crate) encrypt_key: Option<age::x25519::Recipient>,
+    // Where to write the build log, unless STUDY_LOG says otherwise.
+    pub(crate) log_path: Option<String>,
+    // Extra pattern to scrub from snapshots, on top of BUILTIN_REDACTIONS.
+    pub(crate) redact: Option<Regex>,
+    // Template for commit messages, with the placeholders in COMMIT_PLACEHOLDERS.
+    pub(crate) commit_message: Option<String>,
+    // Builds less than this many seconds after the last commit don't take a snapshot, so editors
+    // that run `cargo check` on every keystroke don't flood the history.
+    pub(crate) snapshot_interval_secs: u64,
+    // Milestone tag for the next snapshot, from STUDY_TAG or `tag`.
+    pub(crate) tag: Option<String>,
+    // Extra remotes (mirror1, mirror2, ...) that every push also goes to, with their URLs.
+    pub(crate) mirrors: Vec<(Target, String)>,
+}
+
+impl Config {
+    // The study repo and every mirror.
+    pub(crate) fn targets(&self) -> Vec<&Target> {
+        std::iter::once(&self.target).chain(self.mirrors.iter().map(|(target, _)| target)).collect()
+    }
+}
+
+pub(crate) fn read_config(logger: &mut Logger, manifest_path: &Path) -> Option<Config> {
+    // read config.txt
+    let mut config_file = match fs::File::open(manifest_path.join("config.txt")) {
+        Ok(file) => file,
+        Err(_) => {
+            logger.info("failed to open config.txt");
+            return None;
+        }
+    };
+
+    let mut contents = String::new();
+    if let Err(e) = config_file.read_to_string(&mut contents) {
+        logger.error(&format!("failed to read config.txt: {}", e));
+        return None;
+    }
+
+    parse_config(logger, &contents)
+}
+
+pub(crate) fn parse_config(logger: &mut Logger, text: &str) -> Option<Config> {
+    match parse_config_entries(text).and_then(|entries| config_from_entries(logger, entries)) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            logger.error(&format!("failed to parse config.txt: {}", e));
+            None
+        }
+    }
+}
+
+// Splits config.txt into key/value pairs, using custom parsing logic to avoid making clients
+// depend on serde. Two formats are accepted: TOML-style `key = "value"` lines, and the original
+// `key: value` format with entries separated by commas or newlines, which a flat JSON object also
+// fits. In both, keys and values may be quoted, and only the first separator splits an entry, so
+// a password may contain `:` or `=`. Lines starting with '#' are comments.
+//
+// Of TOML, this understands what config.txt needs: strings, integers, booleans, and for the keys
+// in LIST_KEYS, lists of strings on a single line, which come out joined by ", ". Tables,
+// multi-line strings and lists, dates and floats are not supported.
+pub(crate) fn parse_config_entries(text: &str) -> Result<Vec<(String, String)>, String> {
+    // Drop the braces around a JSON object, keeping everything else where it was.
+    let mut text = text.to_owned();
+    let trimmed = text.trim();
+    if trimmed.starts_with('{') && trimmed.ends_with('}') {
+        let open = text.find('{').unwrap();
+        text.replace_range(open..open + 1, " ");
+        let close = text.rfind('}').unwrap();
+        text.replace_range(close..close + 1, " ");
+    }
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        for (key, value) in parse_config_line(line).map_err(|e| format!("line {}: {}", number + 1, e))? {
+            if entries.iter().any(|(k, _)| *k == key) {
+                return Err(format!("line {}: duplicate key `{}`", number + 1, key));
+            }
+            entries.push((key, value));
+        }
+    }
+    Ok(entries)
+}
+
+// Keys whose TOML value may be a list of strings.
+static LIST_KEYS: &[&str] = &["mirrors"];
+
+fn parse_config_line(line: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        // Tolerates the trailing commas of the legacy format.
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+            continue;
+        }
+
+        let (key, after) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => parse_quoted(rest, quote).ok_or_else(|| format!("unterminated key {}", rest))?,
+            _ => {
+                let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(rest.len());
+                (rest[..end].to_owned(), &rest[end..])
+            }
+        };
+        if key.is_empty() {
+            return Err(format!("expected a key, found `{}`", rest));
+        }
+
+        let after = after.trim_start();
+        let toml = match after.chars().next() {
+            Some('=') => true,
+            Some(':') => false,
+            _ => return Err(format!("expected `=` or `:` after `{}`", key)),
+        };
+        let after = after[1..].trim_start();
+
+        let (value, after) = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => parse_quoted(after, quote).ok_or_else(|| format!("unterminated string for `{}`", key))?,
+            Some('[') if toml => {
+                if !LIST_KEYS.contains(&key.as_str()) {
+                    return Err(format!("`{}` can't be a list", key));
+                }
+                parse_list(after).map_err(|e| format!("{} in the list for `{}`", e, key))?
+            }
+            _ if toml => {
+                // Unquoted TOML values can only be integers or booleans.
+                let end = after.find(|c: char| c.is_whitespace() || c == '#' || c == ',').unwrap_or(after.len());
+                let value = &after[..end];
+                if value.is_empty() {
+                    return Err(format!("missing value for `{}`", key));
+                }
+                if value.parse::<i64>().is_err() && value != "true" && value != "false" {
+                    return Err(format!("value for `{}` must be quoted", key));
+                }
+                (value.to_owned(), &after[end..])
+            }
+            _ => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim_end().to_owned(), &after[end..])
+            }
+        };
+        entries.push((key, value));
+
+        rest = after.trim_start();
+        if toml && rest.starts_with('#') {
+            break;
+        }
+        if !rest.is_empty() && !rest.starts_with(',') {
+            return Err(format!("unexpected `{}` after the value of `{}`", rest, entries.last().unwrap().0));
+        }
+    }
+    Ok(entries)
+}
+
+// Reads a single-line TOML list of strings starting with `[`, returning its items joined by ", "
+// and the text after it.
+fn parse_list(text: &str) -> Result<(String, &str), String> {
+    let mut items = vec![];
+    let mut rest = text[1..].trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            return Ok((items.join(", "), after));
+        }
+        let (item, after) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => parse_quoted(rest, quote).ok_or("unterminated string")?,
+            Some(_) => return Err(format!("expected a quoted string, found `{}`", rest)),
+            None => return Err("missing `]`".to_owned()),
+        };
+        items.push(item);
+        rest = after.trim_start();
+        // TOML allows a trailing comma.
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        }
+        else if !rest.starts_with(']') {
+            return Err(if rest.is_empty() { "missing `]`".to_owned() } else { format!("expected `,` or `]`, found `{}`", rest) });
+        }
+    }
+}
+
+// Reads a string starting with `quote`, returning its contents and the text after it. Double
+// quoted strings understand the usual backslash escapes; single quoted strings are literal.
+fn parse_quoted(text: &str, quote: char) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some((value, &text[i + 1..]));
+        }
+        if c == '\\' && quote == '"' {
+            match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                escaped => value.push(escaped),
+            }
+        }
+        else {
+            value.push(c);
+        }
+    }
+    None
+}
+
+pub(crate) fn config_from_entries(logger: &mut Logger, entries: Vec<(String, String)>) -> Result<Config, String> {
+    let mut entries = entries;
+    let mut take = |key: &str| entries.iter()
+                                      .position(|(k, _)| k == key)
+                                      .map(|i| entries.remove(i).1);
+
+    let participant_id = take("participant_id").ok_or("missing participant_id")?;
+    let project = take("project").ok_or("missing project")?;
+    // Both end up in the remote URL, so only allow characters that can't change its meaning.
+    if !is_safe_name(&participant_id) {
+        return Err(format!("invalid participant_id {:?} (allowed: A-Z a-z 0-9 . _ -)", participant_id));
+    }
+    if !is_safe_name(&project) {
+        return Err(format!("invalid project {:?} (allowed: A-Z a-z 0-9 . _ -)", project));
+    }
+
+    let server = env_setting(SERVER_VAR).or_else(|| take("server")).unwrap_or_else(|| SERVER.to_owned());
+    if server.is_empty() || server.contains(|c: char| c.is_whitespace() || c == '/' || c == '@') {
+        return Err(format!("invalid server {:?} (expected a host name, optionally with a port)", server));
+    }
+    let remote = env_setting(REMOTE_VAR).or_else(|| take("remote")).unwrap_or_else(|| DEFAULT_REMOTE.to_owned());
+    if !is_safe_ref_name(&remote) {
+        return Err(format!("invalid remote {:?}", remote));
+    }
+    let branch = env_setting(BRANCH_VAR).or_else(|| take("branch")).unwrap_or_else(|| DEFAULT_BRANCH.to_owned());
+    if !is_safe_ref_name(&branch) {
+        return Err(format!("invalid branch {:?}", branch));
+    }
+
+    let enabled = parse_switch("enabled", take("enabled"))?.unwrap_or(true)
+                  && parse_switch("consent", take("consent"))?.unwrap_or(true)
+                  && !parse_switch("opt_out", take("opt_out"))?.unwrap_or(false);
+    let redact = match take("redact").map(|pattern| Regex::new(&pattern)) {
+        None => None,
+        Some(Ok(regex)) => Some(regex),
+        Some(Err(e)) => return Err(format!("invalid redact pattern: {}", e)),
+    };
+    let commit_message = take("commit_message");
+    if let Some(template) = &commit_message {
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').map_or(rest.len(), |end| start + end + 1);
+            if !COMMIT_PLACEHOLDERS.contains(&&rest[start..end]) {
+                return Err(format!("unknown placeholder {} in commit_message (allowed: {})", &rest[start..end], COMMIT_PLACEHOLDERS.join(" ")));
+            }
+            rest = &rest[end..];
+        }
+    }
+    let snapshot_interval_secs = match take("snapshot_interval_secs").map(|n| n.parse::<u64>()) {
+        None => 0,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return Err("snapshot_interval_secs must be a non-negative integer".to_owned()),
+    };
+    let tag = env_setting(TAG_VAR).or_else(|| take("tag"));
+    if let Some(name) = tag.as_deref().filter(|name| !is_safe_ref_name(name)) {
+        return Err(format!("invalid tag {:?}", name));
+    }
+    // A list of URLs, or in a string separated by whitespace or commas, pushed to with the
+    // participant's own credentials.
+    let mut mirrors = vec![];
+    for url in take("mirrors").iter().flat_map(|urls| urls.split(|c: char| c == ',' || c.is_whitespace())).filter(|url| !url.is_empty()) {
+        if !url.contains("://") && !Path::new(url).is_absolute() {
+            return Err(format!("invalid mirror {:?} (expected a URL or an absolute path)", url));
+        }
+        let target = Target { remote: format!("mirror{}", mirrors.len() + 1), branch: branch.clone() };
+        mirrors.push((target, url.to_owned()));
+    }
+    let squash_after = match take("squash_after").map(|n| n.parse::<usize>()) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => return Err("squash_after must be a non-negative integer".to_owned()),
+    };
+    let squash_keep = match take("squash_keep").map(|n| n.parse::<usize>()) {
+        None => 0,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return Err("squash_keep must be a non-negative integer".to_owned()),
+    };
+    let max_size_mb = match take("max_size_mb").map(|n| n.parse::<u64>()) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => return Err("max_size_mb must be a non-negative integer".to_owned()),
+    };
+    let skip_artifacts = parse_switch("skip_artifacts", take("skip_artifacts"))?.unwrap_or(false);
+    let capture_artifacts = parse_switch("capture_artifacts", take("capture_artifacts"))?.unwrap_or(false);
+    let encrypt_key = match take("encrypt_key").map(|key| key.parse::<age::x25519::Recipient>()) {
+        None => None,
+        Some(Ok(key)) => Some(key),
+        Some(Err(e)) => return Err(format!("invalid encrypt_key (expected an age public key starting with age1): {}", e)),
+    };
+
+    let config = Config {
+        participant_id,
+        git_password: take("git_password"),
+        ssh_key: take("ssh_key"),
+        project,
+        server,
+        target: Target { remote, branch },
+        enabled,
+        squash_after,
+        squash_keep,
+        max_size_mb,
+        skip_artifacts,
+        capture_artifacts,
+        encrypt_key,
+        log_path: take("log_path"),
+        redact,
+        commit_message,
+        snapshot_interval_secs,
+        tag,
+        mirrors,
+    };
+    // Unknown keys are most likely typos, but may also be options of a newer version of this
+    // script, so they don't stop the build.
+    for (key, _) in entries {
+        logger.warn(&format!("ignoring unknown key `{}` in config.txt", key));
+    }
+    Ok(config)
+}
+
+fn parse_switch(key: &str, value: Option<String>) -> Result<Option<bool>, String> {
+    match value.as_deref() {
+        None => Ok(None),
+        Some("true") | Some("yes") => Ok(Some(true)),
+        Some("false") | Some("no") => Ok(Some(false)),
+        Some(other) => Err(format!("{} must be one of true, false, yes or no, not {:?}", key, other)),
+    }
+}
+
+// Reads an environment variable that overrides a config.txt setting; empty counts as unset.
+fn env_setting(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+// A safe name that git also accepts as part of a ref name.
+fn is_safe_ref_name(name: &str) -> bool {
+    is_safe_name(name) && !name.starts_with('.') && !name.starts_with('-') && !name.contains("..") && !name.ends_with(".lock")
+}
+
+// Checks that a name matches ^[A-Za-z0-9._-]+$.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_read_config() {
+        let text =     
+            "\"participant_id\": \"592089\",
+            \"git_password\": \"985613\",
+            \"project\":\"p1\"";
+        let mut opt = Logger::none();
+        let config = parse_config(&mut opt, text);
+        assert!(config.is_some());
+    }   
+
+    #[test]
+    fn test_read_config_squash() {
+        let text = "participant_id: 592089, git_password: 985613, project: p1, squash_after: 100, squash_keep: 5";
+        let mut opt = Logger::none();
+        let config = parse_config(&mut opt, text).unwrap();
+        assert_eq!(config.squash_after, Some(100));
+        assert_eq!(config.squash_keep, 5);
+    }
+
+    #[test]
+    fn test_read_config_valid_id() {
+        let text = "participant_id: student-01.a_b, git_password: 985613, project: p1";
+        let mut opt = Logger::none();
+        let config = parse_config(&mut opt, text).unwrap();
+        assert_eq!(config.participant_id, "student-01.a_b");
+    }
+
+    #[test]
+    fn test_read_config_id_with_slash() {
+        let text = "participant_id: 592089/evil, git_password: 985613, project: p1";
+        let mut logger = Logger::none();
+        assert!(parse_config(&mut logger, text).is_none());
+    }
+
+    #[test]
+    fn test_read_config_empty_project() {
+        let text = "participant_id: 592089, git_password: 985613, project: ";
+        let mut logger = Logger::none();
+        assert!(parse_config(&mut logger, text).is_none());
+    }
+
+    #[test]
+    fn test_config_template_parses() {
+        let filled_in = CONFIG_TEMPLATE.replace("<your participant id>", "592089")
+                                       .replace("<your git password>", "985613")
+                                       .replace("<your project name>", "p1");
+        let mut opt = Logger::none();
+        let config = parse_config(&mut opt, &filled_in);
+        assert!(config.is_some());
+
+        // An unedited copy of the template must not be mistaken for a real config.
+        let mut logger = Logger::none();
+        assert!(parse_config(&mut logger, CONFIG_TEMPLATE).is_none());
+    }
+
+    #[test]
+    fn test_config_template_not_overwritten() {
+        let dir = TempDir::new("config_template");
+        fs::write(dir.join("config.txt.example"), "mine").unwrap();
+
+        let mut opt = Logger::none();
+        write_config_template(&mut opt, &dir);
+
+        assert_eq!(fs::read_to_string(dir.join("config.txt.example")).unwrap(), "mine");
+        assert!(!dir.join("config.txt").exists());
+    }
+
+    #[test]
+    fn test_read_config_toml() {
+        let text = "# study settings
+            participant_id = \"592089\"
+            git_password = 'p4ss:w=rd#1'   # literal string
+            project = \"p1\"
+            squash_after = 100
+            opt_out = false
+            branch = \"snapshots\"
+            mirrors = [\"https://backup.example.com/p1.git\", '/srv/git/p1.git', ]  # trailing comma";
+        let mut logger = Logger::none();
+        let config = parse_config(&mut logger, text).unwrap();
+        assert_eq!(config.git_password.as_deref(), Some("p4ss:w=rd#1"));
+        assert_eq!(config.squash_after, Some(100));
+        assert_eq!(config.server, SERVER);
+        assert_eq!(config.target.branch, "snapshots");
+        assert_eq!(config.target.remote, DEFAULT_REMOTE);
+        assert!(config.enabled);
+        let mirrors: Vec<&str> = config.mirrors.iter().map(|(_, url)| url.as_str()).collect();
+        assert_eq!(mirrors, ["https://backup.example.com/p1.git", "/srv/git/p1.git"]);
+        assert!(parse_config(&mut logger, "participant_id = \"1\"\nproject = \"p1\"\nmirrors = []").unwrap().mirrors.is_empty());
+    }
+
+    #[test]
+    fn test_read_config_legacy_and_json() {
+        let mut logger = Logger::none();
+        let legacy = parse_config(&mut logger, "participant_id: 592089, git_password: a:b, project: p1, opt_out: true").unwrap();
+        assert_eq!(legacy.git_password.as_deref(), Some("a:b"));
+        assert!(!legacy.enabled);
+
+        let json = "{\n  \"participant_id\": \"592089\",\n  \"git_password\": \"say \\\"hi\\\"\",\n  \"project\": \"p1\"\n}";
+        let json = parse_config(&mut logger, json).unwrap();
+        assert_eq!(json.git_password.as_deref(), Some("say \"hi\""));
+        assert_eq!(json.target.branch, DEFAULT_BRANCH);
+    }
+
+    #[test]
+    fn test_read_config_errors() {
+        let error = |text: &str| parse_config_entries(text).and_then(|entries| config_from_entries(&mut Logger::none(), entries)).err().unwrap();
+        assert_eq!(error("participant_id = \"1\""), "missing project");
+        assert_eq!(error("project = \"p1\""), "missing participant_id");
+        assert_eq!(error("participant_id = \"1\"\nproject = p1"), "line 2: value for `project` must be quoted");
+        assert_eq!(error("participant_id = \"1\"\n\nproject \"p1\""), "line 3: expected `=` or `:` after `project`");
+        assert_eq!(error("participant_id = \"1\"\ngit_password = \"abc"), "line 2: unterminated string for `git_password`");
+        assert_eq!(error("participant_id: 1, participant_id: 2"), "line 1: duplicate key `participant_id`");
+        assert_eq!(error("participant_id: 1, project: p1, opt_out: maybe"), "opt_out must be one of true, false, yes or no, not \"maybe\"");
+        assert_eq!(error("participant_id: 1, project: p1, branch: ../main"), "invalid branch \"../main\"");
+        assert_eq!(error("participant_id = \"1\"\nproject = [\"p1\"]"), "line 2: `project` can't be a list");
+        assert_eq!(error("participant_id = \"1\"\nmirrors = [\"/a\" \"/b\"]"), "line 2: expected `,` or `]`, found `\"/b\"]` in the list for `mirrors`");
+        assert_eq!(error("participant_id = \"1\"\nmirrors = [\"/a\","), "line 2: missing `]` in the list for `mirrors`");
+        assert_eq!(error("participant_id = \"1\"\nmirrors = [/a]"), "line 2: expected a quoted string, found `/a]` in the list for `mirrors`");
+    }
+
+    #[test]
+    fn test_consent_switches() {
+        let mut logger = Logger::none();
+        let enabled = |logger: &mut Logger, extra: &str| parse_config(logger, &format!("participant_id: 1, project: p1{}", extra)).unwrap().enabled;
+        assert!(enabled(&mut logger, ""));
+        assert!(enabled(&mut logger, ", consent: yes, enabled: true"));
+        assert!(!enabled(&mut logger, ", enabled: false"));
+        assert!(!enabled(&mut logger, ", consent: no"));
+        assert!(!enabled(&mut logger, ", consent: yes, opt_out: true"));
+    }
+
+    #[test]
+    fn test_announce_collection_once() {
+        let dir = TempDir::new("study_notice");
+        let marker = dir.join("notice");
+        let mut logger = Logger::none();
+        assert!(announce_collection(&mut logger, &marker, true));
+        assert!(!announce_collection(&mut logger, &marker, true));
+        assert!(announce_collection(&mut logger, &marker, false));
+        assert!(!announce_collection(&mut logger, &marker, false));
+    }
+
+    #[test]
+    fn test_commit_message_placeholders() {
+        let mut logger = Logger::none();
+        assert!(parse_config(&mut logger, "participant_id: 1, project: p1, commit_message: '{kind} at {timestamp}'").is_some());
+        let error = parse_config_entries("participant_id: 1, project: p1, commit_message: '{kind} by {user}'").and_then(|entries| config_from_entries(&mut logger, entries));
+        assert!(error.err().unwrap().starts_with("unknown placeholder {user} in commit_message"));
+    }
+
+    #[test]
+    fn test_read_config_snapshot_interval() {
+        let mut logger = Logger::none();
+        assert_eq!(test_config().snapshot_interval_secs, 0);
+        let config = parse_config(&mut logger, "participant_id: 1, project: p1, snapshot_interval_secs: 60").unwrap();
+        assert_eq!(config.snapshot_interval_secs, 60);
+        assert!(parse_config(&mut logger, "participant_id: 1, project: p1, snapshot_interval_secs: soon").is_none());
+    }
+}
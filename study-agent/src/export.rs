@@ -0,0 +1,208 @@
+// Reading the changelog back, for `cargo study export` and `cargo study status`.
+
+use std::path::Path;
+use std::io::Write;
+
+use git2::Repository;
+
+use crate::copy::AGE_HEADER;
+use crate::logger::format_timestamp;
+use crate::reports::json_string;
+
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+}
+
+// `cargo study export [--csv] [<changelog repo>]`: writes one event per changelog commit, oldest
+// first, for loading straight into pandas and the like: the commit hash and time, the first line
+// of its message, the files it changed with line counts, and the rustc version it was built with.
+// Works on any clone of a changelog, not just the one in this project.
+pub fn export(changelog_path: &Path, format: ExportFormat, out: &mut impl Write) -> Result<(), String> {
+    let events = changelog_events(changelog_path).map_err(|e| format!("failed to read {}: {}", changelog_path.display(), e))?;
+    let written = match format {
+        ExportFormat::Jsonl => events.iter().try_for_each(|event| writeln!(out, "{}", event.to_json())),
+        ExportFormat::Csv => {
+            writeln!(out, "commit,timestamp,message,files_changed,insertions,deletions,rustc,files")
+                .and_then(|()| events.iter().try_for_each(|event| writeln!(out, "{}", event.to_csv())))
+        }
+    };
+    written.map_err(|e| format!("failed to write the export: {}", e))
+}
+
+struct ChangelogEvent {
+    commit: String,
+    time: u64,
+    message: String,
+    files: Vec<String>,
+    insertions: usize,
+    deletions: usize,
+    rustc: Option<String>,
+}
+
+impl ChangelogEvent {
+    fn to_json(&self) -> String {
+        let files: Vec<String> = self.files.iter().map(|file| json_string(file)).collect();
+        format!("{{\"commit\": {}, \"timestamp\": {}, \"message\": {}, \"files\": [{}], \"files_changed\": {}, \"insertions\": {}, \"deletions\": {}, \"rustc\": {}}}",
+                json_string(&self.commit),
+                json_string(&format_timestamp(self.time)),
+                json_string(&self.message),
+                files.join(", "),
+                self.files.len(),
+                self.insertions,
+                self.deletions,
+                self.rustc.as_deref().map_or("null".to_owned(), json_string))
+    }
+
+    // Files are joined with `;` so that each event stays a single row.
+    fn to_csv(&self) -> String {
+        let fields = [self.commit.clone(),
+                      format_timestamp(self.time),
+                      self.message.clone(),
+                      self.files.len().to_string(),
+                      self.insertions.to_string(),
+                      self.deletions.to_string(),
+                      self.rustc.clone().unwrap_or_default(),
+                      self.files.join(";")];
+        fields.iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(",")
+    }
+}
+
+// Quotes a CSV field if it needs to be, as in RFC 4180.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    }
+    else {
+        text.to_owned()
+    }
+}
+
+fn changelog_events(changelog_path: &Path) -> Result<Vec<ChangelogEvent>, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    if repo.head().is_err() {
+        return Ok(vec![]);
+    }
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+
+    let mut events = vec![];
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let tree = commit.tree()?;
+        let parent = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&tree), None)?;
+        let stats = diff.stats()?;
+        let files = diff.deltas()
+                        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+                        .map(|path| path.to_string_lossy().replace('\\', "/"))
+                        .collect();
+        let rustc = tree.get_name("rustc.version")
+                        .and_then(|entry| entry.to_object(&repo).ok())
+                        .and_then(|object| object.peel_to_blob().ok())
+                        .filter(|blob| !blob.content().starts_with(AGE_HEADER))
+                        .map(|blob| String::from_utf8_lossy(blob.content()).trim().to_owned());
+        events.push(ChangelogEvent {
+            commit: commit.id().to_string(),
+            time: commit.time().seconds().max(0) as u64,
+            message: commit.summary().ok().flatten().unwrap_or("").to_owned(),
+            files,
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+            rustc,
+        });
+    }
+    Ok(events)
+}
+
+// Reports committed on top of a snapshot by the rustc wrapper and the test runner.
+static REPORT_FILES: &[&str] = &["diagnostics.json", "tests.json"];
+
+pub(crate) struct ChangelogSummary {
+    // Commits that recorded the project, as opposed to only a report.
+    pub(crate) snapshots: usize,
+    // Commit time of the latest commit, in seconds since the Unix epoch.
+    pub(crate) latest: Option<u64>,
+    // Every file at HEAD.
+    pub(crate) files: Vec<String>,
+}
+
+pub(crate) fn changelog_summary(changelog_path: &Path) -> Result<ChangelogSummary, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let head = match repo.head() {
+        Ok(head) => head.peel_to_commit()?,
+        Err(_) => return Ok(ChangelogSummary { snapshots: 0, latest: None, files: vec![] }),
+    };
+
+    let mut snapshots = 0;
+    let mut walk = repo.revwalk()?;
+    walk.push(head.id())?;
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let parent = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+        let report_only = diff.deltas().all(|delta| {
+            delta.new_file().path().is_some_and(|path| REPORT_FILES.iter().any(|f| path == Path::new(f)))
+        });
+        if !report_only || diff.deltas().len() == 0 {
+            snapshots += 1;
+        }
+    }
+
+    let mut files = vec![];
+    head.tree()?.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            files.push(format!("{}{}", dir, entry.name().unwrap_or("?")));
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(ChangelogSummary { snapshots, latest: Some(head.time().seconds().max(0) as u64), files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::commit::commit_to_git;
+    use crate::logger::Logger;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_export() {
+        let root = TempDir::new("study_export");
+        let changelog = root.join("changelog");
+        init_test_repo(&changelog, "unused");
+        let mut logger = Logger::none();
+
+        let mut out = vec![];
+        export(&changelog, ExportFormat::Jsonl, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        fs::write(changelog.join("rustc.version"), "rustc 1.80.0\n").unwrap();
+        fs::write(changelog.join("main.rs"), "fn main() {\n}\n").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", Some("{kind}, first"));
+        fs::write(changelog.join("main.rs"), "fn main() {\n    1;\n}\n").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        export(&changelog, ExportFormat::Jsonl, &mut out).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"message\": \"snapshot, first\""));
+        assert!(lines[0].contains("\"files\": [\"main.rs\", \"rustc.version\"], \"files_changed\": 2, \"insertions\": 3, \"deletions\": 0, \"rustc\": \"rustc 1.80.0\"}"));
+        assert!(lines[1].contains("\"files\": [\"main.rs\"], \"files_changed\": 1, \"insertions\": 1, \"deletions\": 0"));
+
+        let mut out = vec![];
+        export(&changelog, ExportFormat::Csv, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "commit,timestamp,message,files_changed,insertions,deletions,rustc,files");
+        assert!(rows[1].ends_with(",\"snapshot, first\",2,3,0,rustc 1.80.0,main.rs;rustc.version"));
+    }
+}
@@ -0,0 +1,538 @@
+mod commit;
+mod config;
+mod copy;
+mod export;
+mod logger;
+mod push;
+mod reports;
+#[cfg(test)]
+mod test_util;
+
+pub use export::{export, ExportFormat};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+
+use commit::{commit_to_git, compact_changelog, seconds_since_last_commit, squash_history, tag_milestone};
+use config::{Config, announce_collection, read_config, write_config_template};
+use copy::{ARTIFACTS_DIR, ProjectTree, Redactor, capture_artifacts, copy_files_to_changelog, encryption_changed, redaction_changed, remove_deleted_from_changelog};
+use export::changelog_summary;
+use logger::{Logger, format_timestamp, open_log};
+use push::{init_changelog_repo, last_push_path, preflight, push_with_config, read_pending_pushes, remote_url, sync_remote, unpushed_count};
+use reports::{TEST_FLAG, install_rustc_wrapper, is_rustc_wrapper, rustc_wrapper_main, test_main, write_build_meta, write_host_repo, write_rustc_version, write_timing};
+
+static DEBUG: bool = true;
+static SERVER: &str = "git.goto.ucsd.edu";
+static DEFAULT_REMOTE: &str = "origin";
+static DEFAULT_BRANCH: &str = "main";
+// Environment variables that override the server, remote and branch settings in config.txt.
+static SERVER_VAR: &str = "STUDY_SERVER";
+static REMOTE_VAR: &str = "STUDY_REMOTE";
+static BRANCH_VAR: &str = "STUDY_BRANCH";
+// Environment variable holding a personal access token for pushing over HTTPS.
+static TOKEN_VAR: &str = "STUDY_GIT_TOKEN";
+// Environment variable naming a milestone tag for the next snapshot, like `tag` in config.txt.
+static TAG_VAR: &str = "STUDY_TAG";
+
+/* 
+ * The study agent maintains an internal git repository, changelog/, that records every version of
+ * the code that is built. It runs from the build script of the project being studied, which only
+ * calls build_script_main, and can also be run by hand through the cargo-study binary (`cargo
+ * study init|snapshot|flush|status|export`). The repository is pushed to a remote repository on SERVER.
+ * The server, the name of the remote and the branch can be changed in config.txt or through the
+ * STUDY_SERVER, STUDY_REMOTE and STUDY_BRANCH environment variables. All git operations go through libgit2 (the git2 crate),
+ * so a git executable on PATH is not needed.
+ * 
+ * Unfortunately, there is no good way of seeing output from build.rs, since the output is sent
+ * directly to the compiler. Therefore the agent writes timestamped entries to a log file: the path in
+ * the STUDY_LOG environment variable, else `log_path` from config.txt, else log.txt in the temp directory. Debug
+ * entries are only written if the DEBUG flag is true. The log is appended to, and rotated to
+ * <path>.1 once it grows past LOG_MAX_BYTES.
+ *
+ * Pushing can take seconds on a slow network, so it doesn't hold up the build: once the snapshot
+ * is committed, the build script starts a detached copy of itself with --study-flush to do the
+ * push, and that copy logs to the same file. It also pushes to any `mirrors` listed in config.txt,
+ * each with its own retry state and with the participant's own git credentials rather than the
+ * study ones. Afterwards it keeps the changelog small: about once a week it runs `git gc
+ * --aggressive` (if git is installed), and with `max_size_mb` set in config.txt it squashes
+ * already-pushed history whenever .git grows past that size.
+ *
+ * build.rs runs before the crate is compiled, so it can't see the compiler's diagnostics. For
+ * that, the build script also installs a copy of itself as changelog/.git/study-rustc, which acts
+ * as a rustc wrapper when cargo is pointed at it with RUSTC_WRAPPER: it passes everything through
+ * unchanged and commits the diagnostics of this crate's build as diagnostics.json. Run as
+ * `changelog/.git/study-rustc --study-test [args]`, it runs `cargo test [args]` instead and
+ * commits the outcome of every test as tests.json.
+ *
+ * With `encrypt_key` set to an age public key in config.txt, every file copied from the project,
+ * every report and every file the agent generates (rustc.version, build_meta.json, Cargo.lock,
+ * timing.json, host_repo.json) is encrypted for that key before it is committed, so the server
+ * only ever stores ciphertext. File names are kept, so the history still shows which files changed.
+ *
+ * To mark a snapshot as a milestone (a graded submission, say), set STUDY_TAG=<name> or `tag` in
+ * config.txt. The next build tags its snapshot with an annotated tag of that name, which is pushed
+ * along with the branch. A tag is only created once, so the setting can be left in place.
+ */
+
+// Entry point of a project's build script, which passes in its own CARGO_MANIFEST_DIR:
+//
+//     fn main() {
+//         study_agent::build_script_main(std::path::Path::new(env!("CARGO_MANIFEST_DIR")));
+//     }
+//
+// The build script binary is also what runs as study-rustc and with --study-flush, which is why
+// the project directory is baked into it rather than read at run time.
+//
+// Recording is never allowed to break the build it is recording: errors are logged and shown as
+// cargo warnings, and even a panic only ends the recording.
+pub fn build_script_main(manifest_dir: &Path) {
+    if std::env::args().nth(1).as_deref() == Some(TEST_FLAG) {
+        test_main(manifest_dir);
+    }
+    if is_rustc_wrapper() {
+        rustc_wrapper_main(manifest_dir);
+    }
+
+    let recorded = std::panic::catch_unwind(|| {
+        if std::env::args().nth(1).as_deref() == Some(FLUSH_FLAG) {
+            flush(manifest_dir);
+        }
+        else {
+            record_build(manifest_dir);
+        }
+    });
+    if let Err(panic) = recorded {
+        let message = panic.downcast_ref::<&str>()
+                           .map(|message| message.to_string())
+                           .or_else(|| panic.downcast_ref::<String>().cloned())
+                           .unwrap_or_else(|| "unknown error".to_owned());
+        open_log(None).error(&format!("recording failed: {}", message));
+        println!("cargo:warning=study snapshot failed: {}", message);
+    }
+}
+
+fn record_build(manifest_dir: &Path) {
+    let (mut logger, config) = open_project(manifest_dir, true);
+    let config = match config {
+        Some(config) => config,
+        None => {
+            // No configuration file present. Don't do anything, but leave a template behind so it
+            // is clear what a config.txt should look like.
+            if !manifest_dir.join("config.txt").exists() {
+                logger.warn("no config.txt found; changelog recording is disabled until one is created");
+                write_config_template(&mut logger, manifest_dir);
+            }
+            return;
+        }
+    };
+    if let Some(out_dir) = std::env::var_os("OUT_DIR") {
+        announce_collection(&mut logger, &Path::new(&out_dir).join("study_notice"), config.enabled);
+    }
+    if !config.enabled {
+        logger.info("collection is disabled in config.txt; not recording anything");
+        return;
+    }
+
+    if !record_snapshot(&mut logger, manifest_dir, &config, true) {
+        return;
+    }
+    install_rustc_wrapper(&mut logger, &manifest_dir.join("changelog"));
+
+    logger.debug("starting background push...");
+    if let Err(e) = spawn_flush(manifest_dir) {
+        logger.warn(&format!("failed to start background push, pushing now: {}", e));
+        push_with_config(&mut logger, &config, &manifest_dir.join("changelog"));
+    }
+}
+
+// Opens the log and reads config.txt, moving the log to `log_path` if the config has one. In a
+// build script, `cargo_warnings` also shows logged errors as cargo warnings.
+fn open_project(manifest_dir: &Path, cargo_warnings: bool) -> (Logger, Option<Config>) {
+    let mut logger = open_log(None);
+    logger.cargo_warnings = cargo_warnings;
+    logger.debug("opened log...");
+    let config = read_config(&mut logger, manifest_dir);
+    if let Some(path) = config.as_ref().and_then(|c| c.log_path.as_deref()) {
+        logger = open_log(Some(path));
+        logger.cargo_warnings = cargo_warnings;
+    }
+    (logger, config)
+}
+
+// Sets up the changelog if there isn't one yet and snapshots the project into it. With `debounce`,
+// builds within snapshot_interval_secs of the last commit don't take a snapshot. Returns false if
+// the changelog couldn't be used at all.
+fn record_snapshot(logger: &mut Logger, manifest_dir: &Path, config: &Config, debounce: bool) -> bool {
+    let changelog_path = manifest_dir.join("changelog");
+    // Another build (rust-analyzer's `cargo check`, say) may be snapshotting right now. If it
+    // doesn't finish in time, this build's changes simply go into the next snapshot.
+    let _lock = match lock_changelog(logger, &changelog_path, LOCK_WAIT) {
+        Some(lock) => lock,
+        None => return false,
+    };
+    if !open_changelog(logger, &changelog_path, config) {
+        return false;
+    }
+
+    match seconds_since_last_commit(&changelog_path) {
+        Some(elapsed) if debounce && elapsed < config.snapshot_interval_secs => {
+            logger.info(&format!("skipping snapshot: the last commit was {}s ago", elapsed));
+        }
+        _ => take_snapshot(logger, manifest_dir, &changelog_path, config),
+    }
+    if let Some(tag) = &config.tag {
+        tag_milestone(logger, &changelog_path, tag);
+    }
+    true
+}
+
+// Creates the changelog repo, or brings the remote of an existing one in line with config.txt,
+// checking that the study repo can be pushed to either way. Needs the changelog lock.
+fn open_changelog(logger: &mut Logger, changelog_path: &Path, config: &Config) -> bool {
+    logger.debug("creating directory...");
+    // Will error if the directory already exists, but that's okay; we'll just ignore it. A
+    // changelog/ without a repo (left behind by an interrupted first build, say) is set up too.
+    let created = std::fs::create_dir(changelog_path).is_ok();
+    let repo = remote_url(config);
+    if created || !changelog_path.join(".git").exists() {
+        logger.info(&format!("project: {}", config.project));
+
+        if let Err(e) = init_changelog_repo(changelog_path, &config.target, &repo) {
+            logger.error(&format!("failed to initialize changelog repo: {}", e));
+            if created {
+                let _ = std::fs::remove_dir_all(changelog_path);
+            }
+            return false;
+        }
+        preflight(logger, changelog_path, config);
+    }
+    else {
+        // Keeps the remote in sync with config.txt, and replaces URLs with embedded passwords left
+        // behind by older versions of this script.
+        match sync_remote(changelog_path, &config.target, &repo) {
+            Ok(true) => preflight(logger, changelog_path, config),
+            Ok(false) => {}
+            Err(e) => logger.warn(&format!("failed to update remote url: {}", e)),
+        }
+    }
+    // Mirrors are only backups, so they aren't checked up front; failed pushes to them are logged.
+    for (target, url) in &config.mirrors {
+        if let Err(e) = sync_remote(changelog_path, target, url) {
+            logger.warn(&format!("failed to set up mirror {}: {}", target.remote, e));
+        }
+    }
+    true
+}
+
+// `cargo study init`: writes config.txt.example if there is no config.txt yet, and otherwise
+// creates the changelog and checks that the study repo can be reached.
+pub fn init(manifest_dir: &Path) -> Result<(), String> {
+    let (mut logger, config) = open_project(manifest_dir, false);
+    let config = match config {
+        Some(config) => config,
+        None if manifest_dir.join("config.txt").exists() => return Err("config.txt is invalid; see the log for details".to_owned()),
+        None => {
+            write_config_template(&mut logger, manifest_dir);
+            return Err("no config.txt; copy config.txt.example to config.txt and fill it in".to_owned());
+        }
+    };
+    if !config.enabled {
+        return Err("collection is disabled in config.txt".to_owned());
+    }
+    let changelog_path = manifest_dir.join("changelog");
+    let _lock = lock_changelog(&mut logger, &changelog_path, LOCK_WAIT).ok_or("the changelog is locked by another build")?;
+    if !open_changelog(&mut logger, &changelog_path, &config) {
+        return Err("failed to create the changelog; see the log for details".to_owned());
+    }
+    Ok(())
+}
+
+// `cargo study snapshot`: records a snapshot right away, regardless of snapshot_interval_secs,
+// and pushes it.
+pub fn snapshot(manifest_dir: &Path) -> Result<(), String> {
+    let (mut logger, config) = open_project(manifest_dir, false);
+    let config = config.filter(|config| config.enabled).ok_or("collection is not enabled (see config.txt)")?;
+    if !record_snapshot(&mut logger, manifest_dir, &config, false) {
+        return Err("failed to record a snapshot; see the log for details".to_owned());
+    }
+    push_with_config(&mut logger, &config, &manifest_dir.join("changelog"));
+    Ok(())
+}
+
+// `cargo study status`: tells participants what has been collected about them, from what the
+// changelog already records: how many snapshots there are, when they last went out, what is still
+// waiting to be pushed, and which files the latest snapshot contains.
+pub fn status(manifest_dir: &Path) -> Result<String, String> {
+    let (_, config) = open_project(manifest_dir, false);
+    let config = config.ok_or("no valid config.txt; nothing is being recorded")?;
+    let changelog_path = manifest_dir.join("changelog");
+    let mut lines = vec![format!("collection: {}", if config.enabled { "enabled" } else { "disabled" }),
+                         format!("study repo: {}", remote_url(&config))];
+    if !changelog_path.join(".git").exists() {
+        lines.push("nothing recorded yet".to_owned());
+        return Ok(lines.join("\n"));
+    }
+    let summary = changelog_summary(&changelog_path).map_err(|e| format!("failed to read the changelog: {}", e))?;
+    lines.push(format!("snapshots: {}", summary.snapshots));
+    if let Some(time) = summary.latest {
+        lines.push(format!("latest snapshot: {}", format_timestamp(time)));
+    }
+    for target in config.targets() {
+        let last_push = fs::read_to_string(last_push_path(&changelog_path, target)).ok().and_then(|time| time.trim().parse::<u64>().ok());
+        lines.push(format!("last successful push to {}: {}", target.remote, last_push.map_or("never".to_owned(), format_timestamp)));
+        match unpushed_count(&changelog_path, target) {
+            Ok(unpushed) => lines.push(format!("commits not pushed to {} yet: {}", target.remote, unpushed)),
+            Err(e) => lines.push(format!("commits not pushed to {} yet: unknown ({})", target.remote, e.message())),
+        }
+        if let Some(pending) = read_pending_pushes(&changelog_path, target) {
+            lines.push(format!("next push attempt to {}: {} (after {} failure(s))", target.remote, format_timestamp(pending.next_attempt), pending.failures));
+        }
+    }
+    lines.push(format!("files in the latest snapshot ({}):", summary.files.len()));
+    lines.extend(summary.files.iter().map(|file| format!("  {}", file)));
+    Ok(lines.join("\n"))
+}
+
+// How long a build waits for another one to finish with the changelog, and how old a lock has to
+// be before it is assumed to belong to a build that died without releasing it.
+static LOCK_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+static LOCK_STALE_SECS: u64 = 10 * 60;
+
+// Held while changing the changelog repo. The lock file lives in the temp dir rather than the
+// project, so it is never snapshotted, and holds "<pid> <time>" to recognize stale locks.
+struct ChangelogLock {
+    path: PathBuf,
+    owner: String,
+}
+
+impl Drop for ChangelogLock {
+    fn drop(&mut self) {
+        // Only remove the lock if it wasn't taken over as stale in the meantime.
+        if fs::read_to_string(&self.path).is_ok_and(|owner| owner == self.owner) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn lock_path(changelog_path: &Path) -> PathBuf {
+    std::env::temp_dir().join(format!("study-changelog-{:016x}.lock", fnv1a(changelog_path.to_string_lossy().as_bytes())))
+}
+
+// FNV-1a, which unlike DefaultHasher is the same for every build of this script.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Waits up to `wait` for exclusive use of the changelog, or returns None.
+fn lock_changelog(logger: &mut Logger, changelog_path: &Path, wait: std::time::Duration) -> Option<ChangelogLock> {
+    let path = lock_path(changelog_path);
+    let owner = format!("{} {}", std::process::id(), unix_now());
+    let start = std::time::Instant::now();
+    loop {
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                if let Err(e) = file.write_all(owner.as_bytes()) {
+                    logger.warn(&format!("failed to write {}: {}", path.display(), e));
+                }
+                return Some(ChangelogLock { path, owner });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                if is_stale_lock(&holder) {
+                    logger.warn(&format!("removing stale changelog lock held by {:?}", holder));
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                if start.elapsed() >= wait {
+                    logger.info(&format!("skipping: the changelog is locked by {:?}", holder));
+                    return None;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(e) => {
+                logger.error(&format!("failed to create {}: {}", path.display(), e));
+                return None;
+            }
+        }
+    }
+}
+
+// A lock is stale once it is older than LOCK_STALE_SECS or, where that can be checked, its process
+// has exited. A lock that is still being written reads as empty and is not stale.
+fn is_stale_lock(holder: &str) -> bool {
+    let mut fields = holder.split_whitespace().map(|n| n.parse::<u64>());
+    match (fields.next(), fields.next()) {
+        (Some(Ok(pid)), Some(Ok(time))) => {
+            let exited = cfg!(target_os = "linux") && !Path::new(&format!("/proc/{}", pid)).exists();
+            exited || unix_now().saturating_sub(time) > LOCK_STALE_SECS
+        }
+        _ => false,
+    }
+}
+
+fn take_snapshot(logger: &mut Logger, manifest_path: &Path, changelog_path: &Path, config: &Config) {
+    let started = unix_now();
+    let since_previous = seconds_since_last_commit(changelog_path);
+    let encryption_changed = encryption_changed(logger, changelog_path, config.encrypt_key.as_ref());
+    if redaction_changed(logger, changelog_path, config.redact.as_ref()) || encryption_changed {
+        // Copies made with the old settings are removed, so that all of them are made again.
+        logger.info("encryption or redaction settings changed; copying every file again");
+        let empty = ProjectTree { files: BTreeSet::new(), dirs: BTreeSet::new() };
+        remove_deleted_from_changelog(logger, &empty, changelog_path, changelog_path);
+        let _ = fs::remove_dir_all(changelog_path.join(ARTIFACTS_DIR));
+        let _ = fs::remove_file(changelog_path.join("Cargo.lock"));
+    }
+    let redactor = Redactor::new(config.redact.as_ref());
+    let encrypt_key = config.encrypt_key.as_ref();
+    logger.debug("copying files...");
+    copy_files_to_changelog(logger, manifest_path, changelog_path, &redactor, encrypt_key, config.skip_artifacts, config.ssh_key.as_deref());
+    capture_artifacts(logger, manifest_path, changelog_path, config);
+
+    write_rustc_version(logger, changelog_path, &redactor, encrypt_key);
+    write_build_meta(logger, manifest_path, changelog_path, &redactor, encrypt_key);
+    write_timing(logger, changelog_path, started, since_previous, &redactor, encrypt_key);
+    write_host_repo(logger, manifest_path, changelog_path, &redactor, encrypt_key);
+
+    logger.debug("committing to git...");
+    commit_to_git(logger, changelog_path, "snapshot", config.commit_message.as_deref());
+
+    if let Some(squash_after) = config.squash_after {
+        logger.debug("squashing history...");
+        squash_history(logger, changelog_path, &config.targets(), squash_after, config.squash_keep);
+    }
+}
+
+static FLUSH_FLAG: &str = "--study-flush";
+
+// Runs this build script again as a detached process that only pushes. Its output goes nowhere,
+// since cargo would otherwise wait for it before finishing the build.
+fn spawn_flush(manifest_path: &Path) -> std::io::Result<()> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command.arg(FLUSH_FLAG)
+           .current_dir(manifest_path)
+           .stdin(std::process::Stdio::null())
+           .stdout(std::process::Stdio::null())
+           .stderr(std::process::Stdio::null());
+    // Its own process group, so that interrupting cargo doesn't also cancel the push.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+    command.spawn()?;
+    Ok(())
+}
+
+// Entry point for the detached --study-flush process, and `cargo study flush`.
+pub fn flush(manifest_dir: &Path) {
+    let (mut logger, config) = open_project(manifest_dir, false);
+    let changelog_path = manifest_dir.join("changelog");
+    if let Some(config) = config.filter(|config| config.enabled) {
+        logger.debug("pushing...");
+        push_with_config(&mut logger, &config, &changelog_path);
+        compact_changelog(&mut logger, &config, &changelog_path);
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use crate::config::{CONFIG_TEMPLATE, parse_config};
+    use crate::reports::commit_report;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_missing_config_writes_template() {
+        let dir = TempDir::new("missing_config");
+
+        record_build(&dir);
+        assert_eq!(fs::read_to_string(dir.join("config.txt.example")).unwrap(), CONFIG_TEMPLATE);
+        assert!(!dir.join("config.txt").exists());
+        assert!(!dir.join("changelog").exists());
+    }
+
+    #[test]
+    fn test_changelog_lock() {
+        let changelog = std::env::temp_dir().join(format!("study_lock_{}", std::process::id()));
+        let mut logger = Logger::none();
+        let no_wait = std::time::Duration::from_millis(0);
+
+        let lock = lock_changelog(&mut logger, &changelog, no_wait).unwrap();
+        assert!(lock_changelog(&mut logger, &changelog, no_wait).is_none());
+        drop(lock);
+        let lock = lock_changelog(&mut logger, &changelog, no_wait).unwrap();
+        drop(lock);
+        assert!(!lock_path(&changelog).exists());
+
+        // A build that died while holding the lock doesn't block later builds.
+        fs::write(lock_path(&changelog), format!("{} {}", std::process::id(), unix_now() - LOCK_STALE_SECS - 1)).unwrap();
+        let lock = lock_changelog(&mut logger, &changelog, no_wait).unwrap();
+        drop(lock);
+        assert!(!is_stale_lock(""));
+        assert!(!is_stale_lock(&format!("{} {}", std::process::id(), unix_now())));
+    }
+
+    #[test]
+    fn test_record_snapshot_and_status() {
+        let root = TempDir::new("study_record");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("config.txt"), "participant_id: 1, project: p1, server: localhost:1, snapshot_interval_secs: 3600").unwrap();
+        let mut logger = Logger::none();
+        let config = read_config(&mut logger, &root).unwrap();
+
+        assert!(record_snapshot(&mut logger, &root, &config, true));
+        let repo = Repository::open(root.join("changelog")).unwrap();
+        assert_eq!(head_files(&repo).iter().filter(|f| f.as_str() == "src/main.rs").count(), 1);
+
+        // Builds are debounced, snapshots taken on demand are not.
+        fs::write(root.join("src").join("main.rs"), "fn main() { }").unwrap();
+        assert!(record_snapshot(&mut logger, &root, &config, true));
+        assert_eq!(commit_count(&repo, "HEAD"), 1);
+        assert!(record_snapshot(&mut logger, &root, &config, false));
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+
+        // A report on top of a snapshot isn't counted as one.
+        commit_report(&mut logger, &root.join("changelog"), "tests", "{}", &config);
+        assert_eq!(commit_count(&repo, "HEAD"), 3);
+
+        let status = status(&root).unwrap();
+        assert!(status.contains("collection: enabled"));
+        assert!(status.contains("snapshots: 2\n"));
+        assert!(status.contains("last successful push to origin: never"));
+        assert!(status.contains("commits not pushed to origin yet: 3"));
+        assert!(status.contains("\n  src/main.rs"));
+        assert!(status.contains("\n  tests.json"));
+    }
+
+    #[test]
+    fn test_changelog_without_repo() {
+        let root = TempDir::new("study_no_repo");
+        fs::create_dir_all(root.join("changelog")).unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        let mut logger = Logger::none();
+        let config = parse_config(&mut logger, "participant_id: 1, project: p1, server: localhost:1").unwrap();
+
+        assert!(record_snapshot(&mut logger, &root, &config, true));
+        let repo = Repository::open(root.join("changelog")).unwrap();
+        assert!(head_files(&repo).contains(&"main.rs".to_owned()));
+    }
+}
@@ -0,0 +1,151 @@
+// The log file, which is the only way to see what the agent did during a build.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+
+use crate::{DEBUG, unix_now};
+
+static LOG_VAR: &str = "STUDY_LOG";
+static DEFAULT_LOG_NAME: &str = "log.txt";
+static LOG_MAX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+// Writes log entries below `level` nowhere. A logger without a file drops every entry; logging
+// never fails the build. With `cargo_warnings`, errors are also printed as cargo warnings, which
+// only makes sense in a build script.
+pub(crate) struct Logger {
+    file: Option<fs::File>,
+    level: Level,
+    pub(crate) cargo_warnings: bool,
+}
+
+impl Logger {
+    #[cfg(test)]
+    pub(crate) fn none() -> Logger {
+        Logger { file: None, level: Level::Error, cargo_warnings: false }
+    }
+
+    // Opens the log at `path` for appending, first moving it to <path>.1 if it has grown too big.
+    fn open(path: &Path, level: Level) -> Logger {
+        if fs::metadata(path).map(|m| m.len() > LOG_MAX_BYTES).unwrap_or(false) {
+            let mut rotated = path.as_os_str().to_owned();
+            rotated.push(".1");
+            let _ = fs::rename(path, rotated);
+        }
+        let file = fs::OpenOptions::new()
+                                    .create(true)
+                                    .append(true)
+                                    .open(path);
+        Logger { file: file.ok(), level, cargo_warnings: false }
+    }
+
+    fn log(&mut self, level: Level, msg: &str) {
+        if level == Level::Error && self.cargo_warnings {
+            println!("cargo:warning=study snapshot error: {}", msg);
+        }
+        if level < self.level {
+            return;
+        }
+        if let Some(file) = self.file.as_mut() {
+            // The pid tells the build apart from the background push.
+            let _ = writeln!(file, "{} {:<5} [{}] {}", format_timestamp(unix_now()), level.name(), std::process::id(), msg);
+        }
+    }
+
+    pub(crate) fn debug(&mut self, msg: &str) {
+        self.log(Level::Debug, msg);
+    }
+
+    pub(crate) fn info(&mut self, msg: &str) {
+        self.log(Level::Info, msg);
+    }
+
+    pub(crate) fn warn(&mut self, msg: &str) {
+        self.log(Level::Warn, msg);
+    }
+
+    pub(crate) fn error(&mut self, msg: &str) {
+        self.log(Level::Error, msg);
+    }
+}
+
+// Opens the log at STUDY_LOG if it is set, else at `config_path`, else in the temp directory.
+pub(crate) fn open_log(config_path: Option<&str>) -> Logger {
+    let path = std::env::var_os(LOG_VAR).filter(|path| !path.is_empty())
+                                        .map(PathBuf::from)
+                                        .or_else(|| config_path.map(PathBuf::from))
+                                        .unwrap_or_else(|| std::env::temp_dir().join(DEFAULT_LOG_NAME));
+    let level = if DEBUG { Level::Debug } else { Level::Info };
+    Logger::open(&path, level)
+}
+
+// Formats seconds since the Unix epoch as an ISO-8601 UTC timestamp.
+pub(crate) fn format_timestamp(secs: u64) -> String {
+    // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_log_levels_and_rotation() {
+        let dir = TempDir::new("study_log");
+        let path = dir.join("log.txt");
+
+        let mut logger = Logger::open(&path, Level::Info);
+        logger.debug("hidden");
+        logger.warn("shown");
+        drop(logger);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("hidden"));
+        assert!(text.contains(" WARN  ["));
+        assert!(text.trim_end().ends_with("] shown"));
+
+        fs::write(&path, vec![b'x'; LOG_MAX_BYTES as usize + 1]).unwrap();
+        let mut logger = Logger::open(&path, Level::Info);
+        logger.error("fresh");
+        drop(logger);
+        assert_eq!(fs::metadata(dir.join("log.txt.1")).unwrap().len(), LOG_MAX_BYTES + 1);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains('x') && text.contains("fresh"));
+    }
+}
@@ -0,0 +1,466 @@
+// Pushing the changelog to the study repo and its mirrors, and the credentials for that.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::{Cred, PushOptions, RemoteCallbacks, Repository, RepositoryInitOptions};
+
+use crate::{LOCK_WAIT, TOKEN_VAR, lock_changelog, unix_now};
+use crate::config::Config;
+use crate::logger::Logger;
+
+// Pushes to the study repo and every mirror. The study credentials only ever go to the study
+// server; mirrors are pushed to with the participant's own. Takes the changelog lock, since a
+// build may be committing or squashing in the meantime.
+pub(crate) fn push_with_config(logger: &mut Logger, config: &Config, changelog_path: &Path) {
+    let _lock = match lock_changelog(logger, changelog_path, LOCK_WAIT) {
+        Some(lock) => lock,
+        None => return,
+    };
+    match push_auth(config, std::env::var(TOKEN_VAR).ok()) {
+        Some(auth) => git_push(logger, changelog_path, &config.target, &auth),
+        None => logger.warn(&format!("not pushing: no credentials (set ssh_key or git_password in config.txt, or {})", TOKEN_VAR)),
+    }
+    for (target, _) in &config.mirrors {
+        git_push(logger, changelog_path, target, &Auth::Own);
+    }
+}
+
+// Where the changelog is pushed: a remote of the changelog repo and a branch on it.
+pub(crate) struct Target {
+    pub(crate) remote: String,
+    pub(crate) branch: String,
+}
+
+impl Target {
+    fn local_ref(&self) -> String {
+        format!("refs/heads/{}", self.branch)
+    }
+
+    // The remote-tracking ref, which records what the server had after our last push.
+    pub(crate) fn tracking_ref(&self) -> String {
+        format!("refs/remotes/{}/{}", self.remote, self.branch)
+    }
+}
+
+// Creates the changelog repo on the target branch, with the target remote pointing at `remote_url`.
+pub(crate) fn init_changelog_repo(changelog_path: &Path, target: &Target, remote_url: &str) -> Result<(), git2::Error> {
+    let mut opts = RepositoryInitOptions::new();
+    opts.initial_head(&target.branch);
+    let repo = Repository::init_opts(changelog_path, &opts)?;
+    repo.remote(&target.remote, remote_url)?;
+    Ok(())
+}
+
+// Keeps the target remote in sync with config.txt, adding it if the remote was renamed. Returns
+// whether the remote URL changed.
+pub(crate) fn sync_remote(changelog_path: &Path, target: &Target, remote_url: &str) -> Result<bool, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let current = repo.find_remote(&target.remote).map(|remote| remote.url().ok().map(str::to_owned));
+    match current {
+        Ok(url) if url.as_deref() == Some(remote_url) => Ok(false),
+        Ok(_) => repo.remote_set_url(&target.remote, remote_url).map(|_| true),
+        Err(_) => repo.remote(&target.remote, remote_url).map(|_| true),
+    }
+}
+
+// Checks, like `git ls-remote`, that the study repo exists and accepts our credentials for
+// pushing. This runs when the changelog is created and whenever the remote changes, and a failure
+// is reported loudly, since otherwise nothing would be uploaded and nobody would notice.
+pub(crate) fn preflight(logger: &mut Logger, changelog_path: &Path, config: &Config) {
+    let result = match push_auth(config, std::env::var(TOKEN_VAR).ok()) {
+        Some(auth) => try_preflight(changelog_path, &config.target, &auth).map_err(|e| e.message().to_owned()),
+        None => Err(format!("no credentials (set ssh_key or git_password in config.txt, or {})", TOKEN_VAR)),
+    };
+    match result {
+        Ok(()) => logger.info(&format!("preflight: {} is reachable", remote_url(config))),
+        Err(e) => {
+            // The warning below already tells the participant.
+            logger.warn(&format!("preflight failed: {}", e));
+            for line in preflight_warning(config, &e) {
+                println!("cargo:warning={}", line);
+            }
+        }
+    }
+}
+
+fn preflight_warning(config: &Config, error: &str) -> Vec<String> {
+    vec![
+        format!("could not connect to the study repository {}: {}", remote_url(config), error),
+        format!("check participant_id ({}), project ({}) and your password, ssh_key or {} in config.txt", config.participant_id, config.project, TOKEN_VAR),
+        "until this is fixed, snapshots are only kept locally in changelog/".to_owned(),
+    ]
+}
+
+// Connect and read timeouts, so that an unreachable server doesn't hang the build.
+static PREFLIGHT_CONNECT_TIMEOUT_MS: i32 = 5_000;
+static PREFLIGHT_TIMEOUT_MS: i32 = 10_000;
+
+fn try_preflight(changelog_path: &Path, target: &Target, auth: &Auth) -> Result<(), git2::Error> {
+    // SAFETY: these set libgit2 globals, and nothing else in this process uses libgit2 concurrently.
+    unsafe {
+        git2::opts::set_server_connect_timeout_in_milliseconds(PREFLIGHT_CONNECT_TIMEOUT_MS)?;
+        git2::opts::set_server_timeout_in_milliseconds(PREFLIGHT_TIMEOUT_MS)?;
+    }
+    let repo = Repository::open(changelog_path)?;
+    let mut remote = repo.find_remote(&target.remote)?;
+    remote.connect_auth(git2::Direction::Push, Some(auth_callbacks(auth)), None)?;
+    remote.list()?;
+    remote.disconnect()
+}
+
+// How pushes to SERVER authenticate. Credentials are handed to libgit2 when the server asks for
+// them and are never written into the remote URL, where they would end up in .git/config.
+pub(crate) enum Auth {
+    SshKey(PathBuf),
+    UserPass { username: String, password: String },
+    // Whatever the participant's own git setup offers: the SSH agent, or their credential helper.
+    Own,
+}
+
+// Picks the strongest configured credential: an SSH key, then an access token, then the password.
+fn push_auth(config: &Config, token: Option<String>) -> Option<Auth> {
+    if let Some(key) = &config.ssh_key {
+        return Some(Auth::SshKey(PathBuf::from(key)));
+    }
+    token.filter(|token| !token.is_empty())
+         .or_else(|| config.git_password.clone())
+         .map(|password| Auth::UserPass { username: config.participant_id.clone(), password })
+}
+
+pub(crate) fn remote_url(config: &Config) -> String {
+    if config.ssh_key.is_some() {
+        format!("ssh://git@{}/{}/{}.git", config.server, config.participant_id, config.project)
+    }
+    else {
+        format!("https://{}/{}/{}.git", config.server, config.participant_id, config.project)
+    }
+}
+
+// Pushes that failed are retried on later builds, backing off exponentially from
+// PUSH_RETRY_BASE_SECS up to PUSH_RETRY_MAX_SECS so that working offline doesn't make every build
+// wait on the network.
+static PUSH_RETRY_BASE_SECS: u64 = 30;
+static PUSH_RETRY_MAX_SECS: u64 = 15 * 60;
+
+// The retry state for unpushed snapshots, kept in .git/pending_pushes_<remote> so that it is never
+// committed. The file only exists while a push to that remote is outstanding.
+pub(crate) struct PendingPushes {
+    pub(crate) failures: u32,
+    pub(crate) next_attempt: u64, // seconds since the Unix epoch
+}
+
+fn pending_pushes_path(changelog_path: &Path, target: &Target) -> PathBuf {
+    changelog_path.join(".git").join(format!("pending_pushes_{}", target.remote))
+}
+
+// When the last push to a remote went through, for `cargo study status`.
+pub(crate) fn last_push_path(changelog_path: &Path, target: &Target) -> PathBuf {
+    changelog_path.join(".git").join(format!("last_push_{}", target.remote))
+}
+
+pub(crate) fn read_pending_pushes(changelog_path: &Path, target: &Target) -> Option<PendingPushes> {
+    let text = fs::read_to_string(pending_pushes_path(changelog_path, target)).ok()?;
+    let mut fields = text.split_whitespace().map(|n| n.parse::<u64>());
+    match (fields.next(), fields.next()) {
+        (Some(Ok(failures)), Some(Ok(next_attempt))) => Some(PendingPushes { failures: failures as u32, next_attempt }),
+        _ => None,
+    }
+}
+
+fn write_pending_pushes(changelog_path: &Path, target: &Target, pending: &PendingPushes) -> std::io::Result<()> {
+    fs::write(pending_pushes_path(changelog_path, target), format!("{} {}\n", pending.failures, pending.next_attempt))
+}
+
+// Number of local changelog commits that are not on the remote yet.
+pub(crate) fn unpushed_count(changelog_path: &Path, target: &Target) -> Result<usize, git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    if let Ok(pushed) = repo.refname_to_id(&target.tracking_ref()) {
+        walk.hide(pushed)?;
+    }
+    Ok(walk.count())
+}
+
+// Pushes any committed changes to a remote, unless an earlier failure means we are still backing
+// off from it. Every unpushed snapshot goes up with the next successful push. Each remote has its
+// own tracking ref and retry state, so one that was down catches up on its own.
+pub(crate) fn git_push(logger: &mut Logger, changelog_path: &Path, target: &Target, auth: &Auth) {
+    let now = unix_now();
+    let pending = read_pending_pushes(changelog_path, target);
+    match &pending {
+        Some(pending) if now < pending.next_attempt => {
+            logger.info(&format!("not pushing to {}: retrying in {}s", target.remote, pending.next_attempt - now));
+        }
+        _ => match try_git_push(changelog_path, target, auth) {
+            Ok(()) => {
+                let _ = fs::remove_file(pending_pushes_path(changelog_path, target));
+                if let Err(e) = fs::write(last_push_path(changelog_path, target), format!("{}\n", now)) {
+                    logger.warn(&format!("failed to record the time of the push: {}", e));
+                }
+            }
+            Err(e) => {
+                logger.warn(&format!("failed to push to {}: {}", target.remote, e));
+                let failures = pending.map_or(0, |p| p.failures) + 1;
+                let delay = PUSH_RETRY_BASE_SECS.saturating_mul(1 << (failures - 1).min(16)).min(PUSH_RETRY_MAX_SECS);
+                let retry = PendingPushes { failures, next_attempt: now + delay };
+                if let Err(e) = write_pending_pushes(changelog_path, target, &retry) {
+                    logger.error(&format!("failed to record pending push: {}", e));
+                }
+            }
+        },
+    }
+
+    match unpushed_count(changelog_path, target) {
+        Ok(0) => {}
+        Ok(n) => logger.info(&format!("{} snapshot(s) pending upload to {}", n, target.remote)),
+        Err(e) => logger.warn(&format!("failed to count pending snapshots: {}", e)),
+    }
+}
+
+// Builds the callbacks that supply `auth` to libgit2.
+fn auth_callbacks(auth: &Auth) -> RemoteCallbacks<'_> {
+    let mut asked = false;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username_from_url, allowed| credential(auth, &mut asked, url, username_from_url, allowed));
+    callbacks
+}
+
+// Answers a credential request from libgit2. libgit2 asks again after a rejected credential, so
+// only the first request is answered to avoid retrying forever. For an SSH URL without a user,
+// libgit2 first asks for just the username and only then for the key; that request doesn't count.
+fn credential(auth: &Auth, asked: &mut bool, url: &str, username_from_url: Option<&str>, allowed: git2::CredentialType) -> Result<Cred, git2::Error> {
+    if allowed == git2::CredentialType::USERNAME {
+        return Cred::username(username_from_url.unwrap_or("git"));
+    }
+    if *asked {
+        return Err(git2::Error::from_str("authentication failed"));
+    }
+    *asked = true;
+    match auth {
+        Auth::SshKey(key) => Cred::ssh_key(username_from_url.unwrap_or("git"), None, key, None),
+        Auth::UserPass { username, password } => Cred::userpass_plaintext(username, password),
+        Auth::Own if allowed.contains(git2::CredentialType::SSH_KEY) => Cred::ssh_key_from_agent(username_from_url.unwrap_or("git")),
+        Auth::Own => Cred::credential_helper(&git2::Config::open_default()?, url, username_from_url),
+    }
+}
+
+// After a squash the remote history has to be replaced, which is detected by the tracking ref no
+// longer being part of our history. Like `git push --force-with-lease`, this only happens if the remote
+// branch is still where we last saw it, so nothing unexpected is overwritten.
+pub(crate) fn try_git_push(changelog_path: &Path, target: &Target, auth: &Auth) -> Result<(), git2::Error> {
+    let repo = Repository::open(changelog_path)?;
+    let mut remote = repo.find_remote(&target.remote)?;
+    let local_ref = target.local_ref();
+    let tracking_ref = target.tracking_ref();
+
+    let head = repo.refname_to_id("HEAD")?;
+    let force = match repo.refname_to_id(&tracking_ref) {
+        Ok(pushed) => pushed != head && !repo.graph_descendant_of(head, pushed)?,
+        Err(_) => false,
+    };
+
+    if force {
+        remote.connect_auth(git2::Direction::Push, Some(auth_callbacks(auth)), None)?;
+        let remote_head = remote.list()?
+                                .iter()
+                                .find(|head| head.name() == local_ref)
+                                .map(|head| head.oid());
+        remote.disconnect()?;
+        let expected = repo.refname_to_id(&tracking_ref).ok();
+        if remote_head != expected {
+            return Err(git2::Error::from_str(&format!("{} on {} has changed since the last push; not forcing", target.branch, target.remote)));
+        }
+    }
+
+    let rejected = std::cell::RefCell::new(None);
+    let mut callbacks = auth_callbacks(auth);
+    callbacks.push_update_reference(|refname, status| {
+        if let Some(msg) = status {
+            *rejected.borrow_mut() = Some(format!("{} rejected: {}", refname, msg));
+        }
+        Ok(())
+    });
+    let mut opts = PushOptions::new();
+    opts.remote_callbacks(callbacks);
+
+    // Milestone tags go along with the branch; tags the remote already has are left alone.
+    let mut refspecs = vec![format!("{}{}:{}", if force { "+" } else { "" }, local_ref, local_ref)];
+    for tag in repo.tag_names(None)?.iter() {
+        if let Some(tag) = tag? {
+            refspecs.push(format!("refs/tags/{}:refs/tags/{}", tag, tag));
+        }
+    }
+    remote.push(&refspecs, Some(&mut opts))?;
+    if let Some(msg) = rejected.borrow_mut().take() {
+        return Err(git2::Error::from_str(&msg));
+    }
+
+    let upstream = format!("{}/{}", target.remote, target.branch);
+    repo.find_branch(&target.branch, git2::BranchType::Local)?.set_upstream(Some(&upstream))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use crate::open_changelog;
+    use crate::commit::commit_to_git;
+    use crate::config::parse_config;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_pending_pushes_retry() {
+        let root = TempDir::new("pending_pushes");
+        let changelog = root.join("changelog");
+        let remote = root.join("remote.git");
+        fs::create_dir_all(&changelog).unwrap();
+        let mut logger = Logger::none();
+        init_test_repo(&changelog, remote.to_str().unwrap());
+        let auth = Auth::UserPass { username: "592089".to_owned(), password: String::new() };
+
+        // The remote doesn't exist yet, as if we were offline.
+        fs::write(changelog.join("main.rs"), "version 0").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+        let pending = read_pending_pushes(&changelog, &test_target()).unwrap();
+        assert_eq!(pending.failures, 1);
+        assert!(pending.next_attempt >= unix_now() + PUSH_RETRY_BASE_SECS - 1);
+
+        // While backing off, builds don't try to push.
+        Repository::init_bare(&remote).unwrap();
+        fs::write(changelog.join("main.rs"), "version 1").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+        assert_eq!(read_pending_pushes(&changelog, &test_target()).unwrap().failures, 1);
+        assert_eq!(unpushed_count(&changelog, &test_target()).unwrap(), 2);
+
+        // Once the retry is due, everything queued up goes out and the marker is cleared.
+        write_pending_pushes(&changelog, &test_target(), &PendingPushes { failures: 1, next_attempt: 0 }).unwrap();
+        git_push(&mut logger, &changelog, &test_target(), &auth);
+        assert!(read_pending_pushes(&changelog, &test_target()).is_none());
+        assert!(last_push_path(&changelog, &test_target()).exists());
+        assert_eq!(unpushed_count(&changelog, &test_target()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_push_auth() {
+        let mut opt = Logger::none();
+        let config = parse_config(&mut opt, "participant_id: 592089, git_password: 985613, project: p1").unwrap();
+        assert_eq!(remote_url(&config), "https://git.goto.ucsd.edu/592089/p1.git");
+        assert!(matches!(push_auth(&config, None), Some(Auth::UserPass { password, .. }) if password == "985613"));
+        // A token takes priority over the password.
+        assert!(matches!(push_auth(&config, Some("tok".to_owned())), Some(Auth::UserPass { password, .. }) if password == "tok"));
+
+        let config = parse_config(&mut opt, "participant_id: 592089, ssh_key: /home/me/.ssh/study, project: p1").unwrap();
+        assert_eq!(remote_url(&config), "ssh://git@git.goto.ucsd.edu/592089/p1.git");
+        assert!(matches!(push_auth(&config, Some("tok".to_owned())), Some(Auth::SshKey(_))));
+
+        let config = parse_config(&mut opt, "participant_id: 592089, project: p1").unwrap();
+        assert!(push_auth(&config, None).is_none());
+    }
+
+    #[test]
+    fn test_credential_for_url_without_user() {
+        // A mirror like ssh://host/repo.git: libgit2 asks for the username first, then for the key.
+        let url = "ssh://example.com/repo.git";
+        let mut asked = false;
+        let cred = credential(&Auth::Own, &mut asked, url, None, git2::CredentialType::USERNAME).unwrap();
+        assert_eq!(cred.credtype(), git2::CredentialType::USERNAME.bits());
+        assert!(!asked);
+        let cred = credential(&Auth::Own, &mut asked, url, None, git2::CredentialType::SSH_KEY).unwrap();
+        assert_eq!(cred.credtype(), git2::CredentialType::SSH_KEY.bits());
+        // A rejected key isn't offered again.
+        assert!(credential(&Auth::Own, &mut asked, url, None, git2::CredentialType::SSH_KEY).is_err());
+
+        let mut asked = false;
+        let auth = Auth::UserPass { username: "1".to_owned(), password: "p".to_owned() };
+        assert!(credential(&auth, &mut asked, "https://example.com/repo.git", None, git2::CredentialType::USER_PASS_PLAINTEXT).is_ok());
+        assert!(credential(&auth, &mut asked, "https://example.com/repo.git", None, git2::CredentialType::USER_PASS_PLAINTEXT).is_err());
+    }
+
+    #[test]
+    fn test_sync_remote_renamed() {
+        let changelog = TempDir::new("study_remote");
+        init_test_repo(&changelog, "https://example.com/a.git");
+
+        let mut logger = Logger::none();
+        let config = parse_config(&mut logger, "participant_id: 1, project: p1, server: git.example.edu:8443, remote: study, branch: snapshots").unwrap();
+        assert_eq!(remote_url(&config), "https://git.example.edu:8443/1/p1.git");
+        sync_remote(&changelog, &config.target, &remote_url(&config)).unwrap();
+
+        let repo = Repository::open(&changelog).unwrap();
+        assert_eq!(repo.find_remote("study").unwrap().url().unwrap(), "https://git.example.edu:8443/1/p1.git");
+        assert_eq!(repo.find_remote("origin").unwrap().url().unwrap(), "https://example.com/a.git");
+        assert_eq!(config.target.tracking_ref(), "refs/remotes/study/snapshots");
+    }
+
+    #[test]
+    fn test_preflight() {
+        let root = TempDir::new("study_preflight");
+        let server = root.join("server.git");
+        Repository::init_bare(&server).unwrap();
+        let changelog = root.join("changelog");
+        init_test_repo(&changelog, server.to_str().unwrap());
+        let auth = Auth::UserPass { username: "1".to_owned(), password: "secret".to_owned() };
+
+        try_preflight(&changelog, &test_target(), &auth).unwrap();
+
+        let target = test_target();
+        assert!(sync_remote(&changelog, &target, server.to_str().unwrap()).is_ok_and(|changed| !changed));
+        let missing = root.join("missing.git");
+        assert!(sync_remote(&changelog, &target, missing.to_str().unwrap()).unwrap());
+        assert!(try_preflight(&changelog, &target, &auth).is_err());
+
+        let warning = preflight_warning(&test_config(), "authentication failed");
+        assert!(warning[0].ends_with("/1/p1.git: authentication failed"));
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let root = TempDir::new("study_mirrors");
+        let changelog = root.join("changelog");
+        let (backup, lab) = (root.join("backup.git"), root.join("lab.git"));
+        Repository::init_bare(&backup).unwrap();
+        let text = format!("participant_id = \"1\"\nproject = \"p1\"\nserver = \"localhost:1\"\ngit_password = \"x\"\nmirrors = \"{}, {}\"\n",
+                           backup.display(), lab.display());
+        let mut logger = Logger::none();
+        let config = parse_config(&mut logger, &text).unwrap();
+        assert_eq!(config.mirrors.len(), 2);
+        assert_eq!(config.mirrors[1].0.remote, "mirror2");
+
+        let repo = init_test_repo(&changelog, "unused");
+        assert!(open_changelog(&mut logger, &changelog, &config));
+        fs::write(changelog.join("main.rs"), "version 0").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        // The study server and the lab mirror are down; the backup still gets the snapshot.
+        push_with_config(&mut logger, &config, &changelog);
+        assert_eq!(commit_count(&Repository::open_bare(&backup).unwrap(), "refs/heads/main"), 1);
+        assert!(read_pending_pushes(&changelog, &config.target).is_some());
+        assert!(read_pending_pushes(&changelog, &config.mirrors[0].0).is_none());
+        assert!(read_pending_pushes(&changelog, &config.mirrors[1].0).is_some());
+
+        // Once the lab mirror is back, it catches up on everything it missed.
+        fs::write(changelog.join("main.rs"), "version 1").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        Repository::init_bare(&lab).unwrap();
+        write_pending_pushes(&changelog, &config.mirrors[1].0, &PendingPushes { failures: 1, next_attempt: 0 }).unwrap();
+        push_with_config(&mut logger, &config, &changelog);
+        assert_eq!(commit_count(&Repository::open_bare(&lab).unwrap(), "refs/heads/main"), 2);
+        assert_eq!(unpushed_count(&changelog, &config.mirrors[1].0).unwrap(), 0);
+        assert_eq!(unpushed_count(&changelog, &config.target).unwrap(), 2);
+        assert_eq!(repo.find_remote("mirror2").unwrap().url().unwrap(), lab.to_str().unwrap());
+
+        // Mirrors don't get the study password, so they are pushed to without one just the same.
+        let config = parse_config(&mut logger, &text.replace("git_password = \"x\"\n", "")).unwrap();
+        fs::write(changelog.join("main.rs"), "version 2").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        push_with_config(&mut logger, &config, &changelog);
+        assert_eq!(commit_count(&Repository::open_bare(&backup).unwrap(), "refs/heads/main"), 3);
+
+        assert!(parse_config(&mut logger, "participant_id = \"1\"\nproject = \"p1\"\nmirrors = \"backup\"\n").is_none());
+    }
+}
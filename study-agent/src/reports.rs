@@ -0,0 +1,599 @@
+// Everything recorded besides the project itself: build metadata written with each snapshot,
+// and the compiler diagnostics and test results committed on top of it.
+
+use std::fs;
+use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+
+use git2::Repository;
+
+use crate::{LOCK_WAIT, lock_changelog, spawn_flush, unix_now};
+use crate::commit::commit_to_git;
+use crate::config::{Config, read_config};
+use crate::copy::{Redactor, copy_file, encrypt, needs_copy};
+use crate::logger::{Logger, format_timestamp, open_log};
+
+static WRAPPER_NAME: &str = "study-rustc";
+
+pub(crate) fn is_rustc_wrapper() -> bool {
+    std::env::current_exe().is_ok_and(|exe| exe.file_stem().is_some_and(|stem| stem == WRAPPER_NAME))
+}
+
+fn rustc_wrapper_path(changelog_path: &Path) -> PathBuf {
+    changelog_path.join(".git").join(format!("{}{}", WRAPPER_NAME, std::env::consts::EXE_SUFFIX))
+}
+
+// Keeps changelog/.git/study-rustc up to date with this build script. The new copy is renamed
+// into place, since the old one may be running for another crate of the same build.
+pub(crate) fn install_rustc_wrapper(logger: &mut Logger, changelog_path: &Path) {
+    let wrapper = rustc_wrapper_path(changelog_path);
+    let installed = std::env::current_exe().and_then(|exe| {
+        let metadata = fs::metadata(&exe)?;
+        if !needs_copy(&metadata, &wrapper) {
+            return Ok(false);
+        }
+        let staged = wrapper.with_extension("new");
+        fs::copy(&exe, &staged)?;
+        fs::File::options().write(true).open(&staged)?.set_modified(metadata.modified()?)?;
+        fs::rename(&staged, &wrapper)?;
+        Ok(true)
+    });
+    match installed {
+        Ok(true) => logger.info(&format!("to record compiler diagnostics, build with RUSTC_WRAPPER={}", wrapper.display())),
+        Ok(false) => {}
+        Err(e) => logger.warn(&format!("failed to install {}: {}", WRAPPER_NAME, e)),
+    }
+}
+
+// Entry point when running as study-rustc, invoked by cargo as `study-rustc <rustc> <args>...`.
+// rustc always runs exactly as cargo asked, and its exit code is passed on. Only the compilation
+// of this crate (not its dependencies, build script or tests) is recorded.
+pub(crate) fn rustc_wrapper_main(manifest_dir: &Path) -> ! {
+    let mut args = std::env::args_os().skip(1);
+    let rustc = args.next().unwrap_or_else(|| "rustc".into());
+    let args: Vec<std::ffi::OsString> = args.collect();
+    let mut command = Command::new(rustc);
+    command.args(&args);
+
+    let ours = std::env::var_os("CARGO_MANIFEST_DIR").is_some_and(|dir| Path::new(&dir) == manifest_dir)
+               && std::env::var("CARGO_CRATE_NAME").is_ok_and(|name| name != "build_script_build")
+               && !args.iter().any(|arg| arg == "--test")
+               && args.iter().any(|arg| arg.to_str().is_some_and(|arg| arg.starts_with("--error-format=json")));
+    let mut logger = open_log(None);
+    let config = if ours { read_config(&mut logger, manifest_dir).filter(|config| config.enabled) } else { None };
+    let config = match config {
+        Some(config) => config,
+        None => std::process::exit(command.status().ok().and_then(|status| status.code()).unwrap_or(101)),
+    };
+
+    // cargo asks rustc for JSON, one diagnostic per line, and renders them itself.
+    let mut child = match command.stderr(std::process::Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{}: failed to run rustc: {}", WRAPPER_NAME, e);
+            std::process::exit(101);
+        }
+    };
+    let mut diagnostics = Vec::new();
+    let mut stderr = std::io::BufReader::new(child.stderr.take().unwrap());
+    let mut line = Vec::new();
+    while matches!(std::io::BufRead::read_until(&mut stderr, b'\n', &mut line), Ok(n) if n > 0) {
+        let _ = std::io::stderr().write_all(&line);
+        if let Ok(text) = std::str::from_utf8(&line) {
+            if text.starts_with('{') && text.contains("\"$message_type\":\"diagnostic\"") {
+                diagnostics.push(text.trim_end().to_owned());
+            }
+        }
+        line.clear();
+    }
+    let code = child.wait().ok().and_then(|status| status.code());
+
+    let changelog_path = manifest_dir.join("changelog");
+    let target = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+    record_diagnostics(&mut logger, &changelog_path, &target, code, &diagnostics, &config);
+    if let Err(e) = spawn_flush(manifest_dir) {
+        logger.warn(&format!("failed to start background push: {}", e));
+    }
+    std::process::exit(code.unwrap_or(101));
+}
+
+// Writes diagnostics.json for the compilation of the current snapshot and commits it on top.
+fn record_diagnostics(logger: &mut Logger, changelog_path: &Path, target: &str, code: Option<i32>, diagnostics: &[String], config: &Config) {
+    let snapshot = Repository::open(changelog_path).and_then(|repo| repo.refname_to_id("HEAD")).ok();
+    let diagnostics = json_list(diagnostics);
+    let json = format!("{{\n  \"target\": {},\n  \"snapshot\": {},\n  \"success\": {},\n  \"exit_code\": {},\n  \"diagnostics\": {}\n}}\n",
+                       json_string(target),
+                       snapshot.map_or("null".to_owned(), |id| json_string(&id.to_string())),
+                       code == Some(0),
+                       code.map_or("null".to_owned(), |code| code.to_string()),
+                       diagnostics);
+    commit_report(logger, changelog_path, "diagnostics", &json, config);
+}
+
+// Writes a report generated after the snapshot was taken into the changelog as <kind>.json and
+// commits it.
+pub(crate) fn commit_report(logger: &mut Logger, changelog_path: &Path, kind: &'static str, json: &str, config: &Config) {
+    let _lock = match lock_changelog(logger, changelog_path, LOCK_WAIT) {
+        Some(lock) => lock,
+        None => return,
+    };
+    let name = format!("{}.json", kind);
+    if let Err(e) = write_generated(changelog_path, &name, json, &Redactor::new(config.redact.as_ref()), config.encrypt_key.as_ref()) {
+        logger.error(&format!("failed to write {}: {}", name, e));
+        return;
+    }
+    commit_to_git(logger, changelog_path, kind, config.commit_message.as_deref());
+}
+
+pub(crate) static TEST_FLAG: &str = "--study-test";
+
+// Entry point for `study-rustc --study-test [args]`, which runs `cargo test [args]` in this
+// project, passing its output through, and then records which tests passed.
+pub(crate) fn test_main(manifest_dir: &Path) -> ! {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command.arg("test")
+           .args(std::env::args_os().skip(2))
+           .current_dir(manifest_dir)
+           .stdout(std::process::Stdio::piped());
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{}: failed to run cargo test: {}", WRAPPER_NAME, e);
+            std::process::exit(101);
+        }
+    };
+
+    let mut outcomes = Vec::new();
+    let stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    for line in std::io::BufRead::lines(stdout).map_while(Result::ok) {
+        println!("{}", line);
+        if let Some(outcome) = parse_test_line(&line) {
+            outcomes.push(outcome);
+        }
+    }
+    let code = child.wait().ok().and_then(|status| status.code());
+
+    let mut logger = open_log(None);
+    if let Some(config) = read_config(&mut logger, manifest_dir).filter(|config| config.enabled) {
+        let changelog_path = manifest_dir.join("changelog");
+        record_tests(&mut logger, &changelog_path, code, &outcomes, &config);
+        if let Err(e) = spawn_flush(manifest_dir) {
+            logger.warn(&format!("failed to start background push: {}", e));
+        }
+    }
+    std::process::exit(code.unwrap_or(101));
+}
+
+// Reads a `test <name> ... <outcome>` line of libtest's output as (name, outcome).
+fn parse_test_line(line: &str) -> Option<(String, String)> {
+    let (name, outcome) = line.strip_prefix("test ")?.rsplit_once(" ... ")?;
+    let outcome = match outcome.trim_end() {
+        "ok" => "passed",
+        "FAILED" => "failed",
+        outcome if outcome.starts_with("ignored") => "ignored",
+        _ => return None,
+    };
+    Some((name.to_owned(), outcome.to_owned()))
+}
+
+// Writes tests.json for the current snapshot and commits it on top.
+fn record_tests(logger: &mut Logger, changelog_path: &Path, code: Option<i32>, outcomes: &[(String, String)], config: &Config) {
+    let snapshot = Repository::open(changelog_path).and_then(|repo| repo.refname_to_id("HEAD")).ok();
+    let tests: Vec<String> = outcomes.iter()
+                                     .map(|(name, outcome)| format!("{{\"name\": {}, \"outcome\": {}}}", json_string(name), json_string(outcome)))
+                                     .collect();
+    let tests = json_list(&tests);
+    let count = |wanted: &str| outcomes.iter().filter(|(_, outcome)| outcome == wanted).count();
+    let json = format!("{{\n  \"snapshot\": {},\n  \"success\": {},\n  \"exit_code\": {},\n  \"passed\": {},\n  \"failed\": {},\n  \"ignored\": {},\n  \"tests\": {}\n}}\n",
+                       snapshot.map_or("null".to_owned(), |id| json_string(&id.to_string())),
+                       code == Some(0),
+                       code.map_or("null".to_owned(), |code| code.to_string()),
+                       count("passed"),
+                       count("failed"),
+                       count("ignored"),
+                       tests);
+    commit_report(logger, changelog_path, "tests", &json, config);
+}
+
+// Quotes a string for a JSON document.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Formats JSON values as a list, one item per line, for a field of a top-level object.
+fn json_list(items: &[String]) -> String {
+    if items.is_empty() {
+        "[]".to_owned()
+    }
+    else {
+        format!("[\n    {}\n  ]", items.join(",\n    "))
+    }
+}
+
+// Writes a file the agent generates into the changelog, redacted and encrypted the same way as
+// the files copied from the project.
+fn write_generated(changelog_path: &Path, name: &str, text: &str, redactor: &Redactor, encrypt_key: Option<&age::x25519::Recipient>) -> std::io::Result<()> {
+    let text = redactor.redact(text).map_or_else(|| text.to_owned(), |(text, _)| text);
+    let contents = match encrypt_key {
+        Some(key) => encrypt(text.as_bytes(), key)?,
+        None => text.into_bytes(),
+    };
+    fs::write(changelog_path.join(name), contents)
+}
+
+pub(crate) fn write_rustc_version(logger: &mut Logger, path: &Path, redactor: &Redactor, encrypt_key: Option<&age::x25519::Recipient>) {
+    // Record Rust version, using the rustc cargo builds with rather than whichever is on PATH
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let rustc_version = match Command::new(&rustc).args(["--version"]).current_dir(path).output() {
+        Ok(output) => output,
+        Err(e) => {
+            logger.error(&format!("failed to run {} --version: {}", Path::new(&rustc).display(), e));
+            return;
+        }
+    };
+    let written = write_generated(path, "rustc.version", &format!("{}\n", String::from_utf8_lossy(&rustc_version.stdout)), redactor, encrypt_key);
+    if let Err(e) = written {
+        logger.error(&format!("failed to write rustc.version: {}", e));
+    }
+}
+
+// Records what is needed to reconstruct the build environment in build_meta.json, and snapshots
+// Cargo.lock even though it is usually ignored. Nothing time-dependent goes in, so an unchanged
+// build doesn't produce a new commit.
+pub(crate) fn write_build_meta(logger: &mut Logger, manifest_path: &Path, changelog_path: &Path, redactor: &Redactor, encrypt_key: Option<&age::x25519::Recipient>) {
+    let output = |program: &str, args: &[&str]| Command::new(program).args(args)
+                                                                     .output()
+                                                                     .ok()
+                                                                     .filter(|output| output.status.success())
+                                                                     .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+    let env = |var: &str| std::env::var(var).ok();
+    let rustc = env("RUSTC").unwrap_or_else(|| "rustc".to_owned());
+    let cargo = env("CARGO").unwrap_or_else(|| "cargo".to_owned());
+    // rustup sets RUSTUP_TOOLCHAIN when cargo runs through its proxy.
+    let toolchain = env("RUSTUP_TOOLCHAIN").or_else(|| output("rustup", &["show", "active-toolchain"]));
+    let mut features: Vec<String> = std::env::vars()
+                                            .filter_map(|(var, _)| var.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase()))
+                                            .collect();
+    features.sort();
+
+    let fields = [
+        ("rustc", output(&rustc, &["--version"])),
+        ("cargo", output(&cargo, &["--version"])),
+        ("toolchain", toolchain),
+        ("host", env("HOST")),
+        ("target", env("TARGET")),
+        ("profile", env("PROFILE")),
+        ("opt_level", env("OPT_LEVEL")),
+        ("debug", env("DEBUG")),
+        ("command", cargo_command()),
+    ];
+    let mut json = String::from("{\n");
+    for (name, value) in fields {
+        json.push_str(&format!("  \"{}\": {},\n", name, value.map_or("null".to_owned(), |value| json_string(&value))));
+    }
+    let features: Vec<String> = features.iter().map(|f| json_string(f)).collect();
+    json.push_str(&format!("  \"features\": {}\n}}\n", json_list(&features)));
+    if let Err(e) = write_generated(changelog_path, "build_meta.json", &json, redactor, encrypt_key) {
+        logger.error(&format!("failed to write build_meta.json: {}", e));
+    }
+
+    let lock = manifest_path.join("Cargo.lock");
+    if let Ok(metadata) = fs::metadata(&lock) {
+        if needs_copy(&metadata, &changelog_path.join("Cargo.lock")) {
+            if let Err(e) = copy_file(&lock, &changelog_path.join("Cargo.lock"), &metadata, redactor, encrypt_key) {
+                logger.error(&format!("failed to copy Cargo.lock: {}", e));
+            }
+        }
+    }
+}
+
+// Records the state of the participant's own git repository, if the project is in one, in
+// host_repo.json: the current branch (null when HEAD is detached), the HEAD commit, whether
+// tracked files have uncommitted changes, and how many files are untracked, not counting the
+// changelog itself.
+pub(crate) fn write_host_repo(logger: &mut Logger, manifest_path: &Path, changelog_path: &Path, redactor: &Redactor, encrypt_key: Option<&age::x25519::Recipient>) {
+    let json = match host_repo_state(manifest_path, changelog_path) {
+        Ok(Some(state)) => state,
+        Ok(None) => "{\n  \"found\": false\n}\n".to_owned(),
+        Err(e) => {
+            logger.warn(&format!("failed to read the project's git repository: {}", e));
+            return;
+        }
+    };
+    if let Err(e) = write_generated(changelog_path, "host_repo.json", &json, redactor, encrypt_key) {
+        logger.error(&format!("failed to write host_repo.json: {}", e));
+    }
+}
+
+fn host_repo_state(manifest_path: &Path, changelog_path: &Path) -> Result<Option<String>, git2::Error> {
+    let repo = match Repository::discover(manifest_path) {
+        Ok(repo) => repo,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => return Ok(None),
+    };
+    let head = repo.head().ok();
+    let branch = head.as_ref().filter(|head| head.is_branch()).and_then(|head| head.shorthand().ok()).map(json_string);
+    let commit = head.as_ref().and_then(|head| head.target()).map(|id| json_string(&id.to_string()));
+
+    let changelog = changelog_path.strip_prefix(&workdir).unwrap_or(changelog_path).to_path_buf();
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(false).exclude_submodules(true);
+    let (mut dirty, mut untracked) = (false, 0);
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        if entry.path().is_ok_and(|path| Path::new(path).starts_with(&changelog)) {
+            continue;
+        }
+        if entry.status().contains(git2::Status::WT_NEW) {
+            untracked += 1;
+        }
+        else if !entry.status().contains(git2::Status::IGNORED) {
+            dirty = true;
+        }
+    }
+    Ok(Some(format!("{{\n  \"found\": true,\n  \"branch\": {},\n  \"head\": {},\n  \"dirty\": {},\n  \"untracked\": {}\n}}\n",
+                    branch.unwrap_or_else(|| "null".to_owned()),
+                    commit.unwrap_or_else(|| "null".to_owned()),
+                    dirty,
+                    untracked)))
+}
+
+// A gap of more than this many seconds between snapshots starts a new work session.
+static SESSION_GAP_SECS: u64 = 30 * 60;
+
+// Records when this build happened in timing.json, for reconstructing work sessions:
+//  - cargo_start: when the cargo command started (Linux only; null elsewhere and outside builds)
+//  - snapshot_start, snapshot_end: when the snapshot started and finished. The build script runs
+//    before the crate is compiled, so the end of the build itself isn't known here; with the rustc
+//    wrapper, the diagnostics.json commit marks it.
+//  - since_previous_secs: time since the previous commit, null for the first snapshot
+//  - session: a counter that goes up by one after a break of more than SESSION_GAP_SECS. It is
+//    kept in .git/session, since timing.json may be encrypted; changelogs from before that carry
+//    it over from their timing.json.
+pub(crate) fn write_timing(logger: &mut Logger, changelog_path: &Path, started: u64, since_previous: Option<u64>, redactor: &Redactor, encrypt_key: Option<&age::x25519::Recipient>) {
+    let session_path = changelog_path.join(".git").join("session");
+    let previous_session = fs::read_to_string(&session_path).ok().and_then(|session| session.trim().parse::<u64>().ok()).or_else(|| {
+        let json = fs::read_to_string(changelog_path.join("timing.json")).ok()?;
+        let rest = json.split("\"session\":").nth(1)?;
+        rest.trim_start().split(|c: char| !c.is_ascii_digit()).next()?.parse::<u64>().ok()
+    });
+    let session = match (previous_session, since_previous) {
+        (Some(session), Some(gap)) if gap <= SESSION_GAP_SECS => session,
+        (Some(session), _) => session + 1,
+        (None, _) => 1,
+    };
+    let cargo_start = if std::env::var_os("OUT_DIR").is_some() { cargo_start_time() } else { None };
+
+    let timestamp = |time: Option<u64>| time.map_or("null".to_owned(), |time| json_string(&format_timestamp(time)));
+    let json = format!("{{\n  \"cargo_start\": {},\n  \"snapshot_start\": {},\n  \"snapshot_end\": {},\n  \"since_previous_secs\": {},\n  \"session\": {}\n}}\n",
+                       timestamp(cargo_start),
+                       timestamp(Some(started)),
+                       timestamp(Some(unix_now())),
+                       since_previous.map_or("null".to_owned(), |secs| secs.to_string()),
+                       session);
+    if let Err(e) = write_generated(changelog_path, "timing.json", &json, redactor, encrypt_key) {
+        logger.error(&format!("failed to write timing.json: {}", e));
+    }
+    if let Err(e) = fs::write(&session_path, format!("{}\n", session)) {
+        logger.warn(&format!("failed to record the session: {}", e));
+    }
+}
+
+// When the cargo process running this build script started, in seconds since the Unix epoch. Its
+// start is given in clock ticks after boot, which are 1/100 s on every Linux that Rust supports.
+fn cargo_start_time() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", std::os::unix::process::parent_id())).ok()?;
+        // The command name in parentheses may contain spaces, so fields are counted after it.
+        let ticks = stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse::<u64>().ok()?;
+        let boot_time = fs::read_to_string("/proc/stat").ok()?
+                                                        .lines()
+                                                        .find_map(|line| line.strip_prefix("btime "))?
+                                                        .trim()
+                                                        .parse::<u64>()
+                                                        .ok()?;
+        Some(boot_time + ticks / 100)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+// The cargo command line that started this build, like "cargo build --release". cargo doesn't
+// tell build scripts, so this is read from the parent process where the OS allows it.
+fn cargo_command() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let cmdline = fs::read(format!("/proc/{}/cmdline", std::os::unix::process::parent_id())).ok()?;
+        let mut args = cmdline.split(|&b| b == 0)
+                              .filter(|arg| !arg.is_empty())
+                              .map(|arg| String::from_utf8_lossy(arg).into_owned());
+        let program = args.next()?;
+        let program = Path::new(&program).file_stem()?.to_string_lossy().into_owned();
+        Some(std::iter::once(program).chain(args).collect::<Vec<String>>().join(" "))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use git2::{Repository, Signature};
+    use crate::copy::copy_files_to_changelog;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\"\\c\nd\u{1}"), "\"a \\\"b\\\"\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn test_record_diagnostics() {
+        let changelog = TempDir::new("study_diagnostics");
+        init_test_repo(&changelog, "https://example.com/a.git");
+        let mut logger = Logger::none();
+        fs::write(changelog.join("main.rs"), "fn main() { 1 + true; }").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        let repo = Repository::open(&changelog).unwrap();
+        let snapshot = repo.refname_to_id("HEAD").unwrap();
+
+        let diagnostic = r#"{"$message_type":"diagnostic","message":"cannot add `bool` to `{integer}`","level":"error"}"#;
+        record_diagnostics(&mut logger, &changelog, "diamondback", Some(1), &[diagnostic.to_owned()], &test_config());
+
+        let json = fs::read_to_string(changelog.join("diagnostics.json")).unwrap();
+        assert!(json.contains(&format!("\"snapshot\": \"{}\"", snapshot)));
+        assert!(json.contains("\"success\": false,\n  \"exit_code\": 1,"));
+        assert!(json.contains(diagnostic));
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+        assert!(head_files(&repo).contains(&"diagnostics.json".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_test_line() {
+        assert_eq!(parse_test_line("test add1 ... ok"), Some(("add1".to_owned(), "passed".to_owned())));
+        assert_eq!(parse_test_line("test tests::overflow ... FAILED"), Some(("tests::overflow".to_owned(), "failed".to_owned())));
+        assert_eq!(parse_test_line("test slow ... ignored, needs nasm"), Some(("slow".to_owned(), "ignored".to_owned())));
+        assert_eq!(parse_test_line("test result: ok. 3 passed; 0 failed"), None);
+        assert_eq!(parse_test_line("running 3 tests"), None);
+    }
+
+    #[test]
+    fn test_record_tests() {
+        let changelog = TempDir::new("study_tests");
+        init_test_repo(&changelog, "https://example.com/a.git");
+        let mut logger = Logger::none();
+        fs::write(changelog.join("main.rs"), "fn main() {}").unwrap();
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+
+        let outcomes = vec![("add1".to_owned(), "passed".to_owned()), ("overflow".to_owned(), "failed".to_owned())];
+        record_tests(&mut logger, &changelog, Some(101), &outcomes, &test_config());
+
+        let json = fs::read_to_string(changelog.join("tests.json")).unwrap();
+        assert!(json.contains("\"success\": false,\n  \"exit_code\": 101,\n  \"passed\": 1,\n  \"failed\": 1,\n  \"ignored\": 0,"));
+        assert!(json.contains("{\"name\": \"overflow\", \"outcome\": \"failed\"}"));
+        let repo = Repository::open(&changelog).unwrap();
+        assert_eq!(commit_count(&repo, "HEAD"), 2);
+    }
+
+    #[test]
+    fn test_write_build_meta() {
+        let root = TempDir::new("build_meta");
+        let project = root.join("project");
+        let changelog = project.join("changelog");
+        let repo = init_test_repo(&changelog, "unused");
+        fs::write(project.join("Cargo.lock"), "version = 3").unwrap();
+        fs::write(project.join(".gitignore"), "target/\nCargo.lock\n").unwrap();
+        fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+        let mut logger = Logger::none();
+
+        write_build_meta(&mut logger, &project, &changelog, &Redactor::new(None), None);
+        let json = fs::read_to_string(changelog.join("build_meta.json")).unwrap();
+        assert!(json.starts_with("{\n  \"rustc\": \"rustc "));
+        assert!(json.contains("\"profile\": "));
+        assert!(json.contains("\"features\": "));
+        assert_eq!(fs::read_to_string(changelog.join("Cargo.lock")).unwrap(), "version = 3");
+
+        // Committed even though the copied .gitignore ignores it.
+        copy_files_to_changelog(&mut logger, &project, &changelog, &Redactor::new(None), None, true, None);
+        commit_to_git(&mut logger, &changelog, "snapshot", None);
+        assert_eq!(head_files(&repo), vec![".gitignore", "Cargo.lock", "build_meta.json", "main.rs"]);
+
+        // Generated files survive the next copy even though they have no source.
+        fs::remove_file(project.join("Cargo.lock")).unwrap();
+        copy_files_to_changelog(&mut logger, &project, &changelog, &Redactor::new(None), None, true, None);
+        assert!(changelog.join("build_meta.json").exists());
+    }
+
+    #[test]
+    fn test_timing_sessions() {
+        let dir = TempDir::new("study_timing");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let mut logger = Logger::none();
+        let timing = || fs::read_to_string(dir.join("timing.json")).unwrap();
+
+        write_timing(&mut logger, &dir, 1700000000, None, &Redactor::new(None), None);
+        assert!(timing().contains("\"snapshot_start\": \"2023-11-14T22:13:20Z\""));
+        assert!(timing().contains("\"since_previous_secs\": null"));
+        assert!(timing().contains("\"session\": 1\n"));
+
+        write_timing(&mut logger, &dir, 1700000060, Some(60), &Redactor::new(None), None);
+        assert!(timing().contains("\"since_previous_secs\": 60"));
+        assert!(timing().contains("\"session\": 1\n"));
+
+        // A long break starts the next session, and the count carries on from there.
+        write_timing(&mut logger, &dir, 1700009000, Some(SESSION_GAP_SECS + 1), &Redactor::new(None), None);
+        assert!(timing().contains("\"session\": 2\n"));
+        write_timing(&mut logger, &dir, 1700009010, Some(10), &Redactor::new(None), None);
+        assert!(timing().contains("\"session\": 2\n"));
+
+        // The count doesn't depend on being able to read timing.json, which may be encrypted.
+        let key = age::x25519::Identity::generate().to_public();
+        write_timing(&mut logger, &dir, 1700020000, Some(SESSION_GAP_SECS + 1), &Redactor::new(None), Some(&key));
+        write_timing(&mut logger, &dir, 1700020010, Some(10), &Redactor::new(None), None);
+        assert!(timing().contains("\"session\": 3\n"));
+    }
+
+    #[test]
+    fn test_host_repo() {
+        let project = TempDir::new("study_host_repo");
+        let changelog = project.join("changelog");
+        fs::create_dir_all(&changelog).unwrap();
+        let mut logger = Logger::none();
+        let host_repo = || fs::read_to_string(changelog.join("host_repo.json")).unwrap();
+
+        write_host_repo(&mut logger, &project, &changelog, &Redactor::new(None), None);
+        assert_eq!(host_repo(), "{\n  \"found\": false\n}\n");
+
+        let repo = Repository::init(&project).unwrap();
+        fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("main.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("student", "student@example.com").unwrap();
+        let commit = repo.commit(Some("HEAD"), &signature, &signature, "start", &tree, &[]).unwrap();
+        index.write().unwrap();
+        let branch = repo.head().unwrap().shorthand().unwrap().to_owned();
+
+        // The changelog inside the project is not counted as an untracked file.
+        write_host_repo(&mut logger, &project, &changelog, &Redactor::new(None), None);
+        assert!(host_repo().contains(&format!("\"branch\": \"{}\"", branch)));
+        assert!(host_repo().contains(&format!("\"head\": \"{}\"", commit)));
+        assert!(host_repo().contains("\"dirty\": false"));
+        assert!(host_repo().contains("\"untracked\": 0"));
+
+        fs::write(project.join("main.rs"), "fn main() { todo!() }").unwrap();
+        fs::write(project.join("notes.txt"), "").unwrap();
+        write_host_repo(&mut logger, &project, &changelog, &Redactor::new(None), None);
+        assert!(host_repo().contains("\"dirty\": true"));
+        assert!(host_repo().contains("\"untracked\": 1"));
+
+        repo.set_head_detached(commit).unwrap();
+        write_host_repo(&mut logger, &project, &changelog, &Redactor::new(None), None);
+        assert!(host_repo().contains("\"branch\": null"));
+    }
+}
@@ -0,0 +1,82 @@
+// Helpers shared by the tests of every module.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Read;
+
+use git2::Repository;
+
+use crate::{DEFAULT_BRANCH, DEFAULT_REMOTE};
+use crate::config::{Config, parse_config};
+use crate::logger::Logger;
+use crate::push::{Target, init_changelog_repo};
+
+// A scratch directory under the temp dir, named after the test and this process so that
+// concurrent test runs don't collide. It starts out empty and is removed when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub(crate) fn test_config() -> Config {
+    parse_config(&mut Logger::none(), "participant_id: 1, project: p1").unwrap()
+}
+
+pub(crate) fn test_target() -> Target {
+    Target { remote: DEFAULT_REMOTE.to_owned(), branch: DEFAULT_BRANCH.to_owned() }
+}
+
+// Creates an empty changelog repo the way main does, pointing origin at `remote`.
+pub(crate) fn init_test_repo(changelog: &Path, remote: &str) -> Repository {
+    init_changelog_repo(changelog, &test_target(), remote).unwrap();
+    Repository::open(changelog).unwrap()
+}
+
+pub(crate) fn head_files(repo: &Repository) -> Vec<String> {
+    let mut files = vec![];
+    let tree = repo.head().unwrap().peel_to_tree().unwrap();
+    tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            files.push(format!("{}{}", dir, entry.name().unwrap()));
+        }
+        git2::TreeWalkResult::Ok
+    }).unwrap();
+    files
+}
+
+pub(crate) fn commit_count(repo: &Repository, refname: &str) -> usize {
+    let mut walk = repo.revwalk().unwrap();
+    walk.push_ref(refname).unwrap();
+    walk.count()
+}
+
+pub(crate) fn decrypt(encrypted: &[u8], identity: &age::x25519::Identity) -> String {
+    let decryptor = match age::Decryptor::new(encrypted).unwrap() {
+        age::Decryptor::Recipients(decryptor) => decryptor,
+        _ => panic!("not encrypted for a recipient"),
+    };
+    let mut text = String::new();
+    decryptor.decrypt(std::iter::once(identity as &dyn age::Identity)).unwrap().read_to_string(&mut text).unwrap();
+    text
+}
@@ -19,15 +19,92 @@ success_tests! {
         file: "even_odd.snek",
         input: "9",
         expected: "9\nfalse\nfalse",
+    },
+    {
+        name: comment_start,
+        file: "comment_start.snek",
+        expected: "3",
+    },
+    {
+        name: comment_end_of_line,
+        file: "comment_end_of_line.snek",
+        expected: "6",
+    },
+    {
+        name: input_default,
+        file: "input_default.snek",
+        expected: "1",
+    },
+    {
+        name: input_given,
+        file: "input_default.snek",
+        input: "5",
+        expected: "6",
+    },
+    {
+        name: input_default_flag,
+        file: "input_default.snek",
+        args: ["--input-default", "7"],
+        expected: "8",
+    },
+    {
+        name: input_default_flag_overridden,
+        file: "input_default.snek",
+        args: ["--input-default", "7", "5"],
+        expected: "6",
     }
 }
 
-runtime_error_tests! {}
+runtime_error_tests! {
+    {
+        name: overflow,
+        file: "overflow.snek",
+        exit_code: 1,
+        expected: "overflow",
+    },
+    {
+        name: invalid_argument,
+        file: "invalid_argument.snek",
+        exit_code: 2,
+        expected: "invalid argument",
+    },
+    {
+        name: input_default_without_value,
+        file: "input_default.snek",
+        args: ["--input-default"],
+        exit_code: 64,
+        expected: "usage",
+    }
+}
 
 static_error_tests! {
     {
         name: duplicate_params,
         file: "duplicate_params.snek",
         expected: "",
+    },
+    {
+        name: comment_only,
+        file: "comment_only.snek",
+        expected: "empty program",
     }
 }
+
+#[test]
+fn check_reports_every_error() {
+    infra::check_all_errors(
+        "two_bad_includes",
+        "two_bad_includes.snek",
+        &["missing_one.snek", "missing_two.snek"],
+    );
+}
+
+#[test]
+fn test_dir_runner() {
+    infra::check_test_dir("tests/runner", &["wrong"], "4 passed, 1 failed");
+}
+
+#[test]
+fn snapshots() {
+    infra::check_snapshots();
+}
@@ -32,6 +32,8 @@ macro_rules! tests {
                 name: $name:ident,
                 file: $file:literal,
                 $(input: $input:literal,)?
+                $(args: [$($arg:literal),* $(,)?],)?
+                $(exit_code: $exit_code:literal,)?
                 expected: $expected:literal $(,)?
                 $(" $(tt:$tt)* ")?
             }
@@ -44,8 +46,14 @@ macro_rules! tests {
                 #[allow(unused_assignments, unused_mut)]
                 let mut input = None;
                 $(input = Some($input);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut args: Vec<&str> = vec![];
+                $(args = vec![$($arg),*];)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut exit_code = None;
+                $(exit_code = Some($exit_code);)?
                 let kind = $crate::infra::TestKind::$kind;
-                $crate::infra::run_test(stringify!($name), $file, input, $expected, kind);
+                $crate::infra::run_test(stringify!($name), $file, input, &args, exit_code, $expected, kind);
             }
         )*
     };
@@ -55,23 +63,27 @@ pub(crate) fn run_test(
     name: &str,
     file: &str,
     input: Option<&str>,
+    args: &[&str],
+    exit_code: Option<i32>,
     expected: &str,
     kind: TestKind,
 ) {
     let file = Path::new("tests").join(file);
+    // The input is the first argument, ahead of any others.
+    let args: Vec<&str> = input.into_iter().chain(args.iter().copied()).collect();
     match kind {
-        TestKind::Success => run_success_test(name, &file, expected, input),
-        TestKind::RuntimeError => run_runtime_error_test(name, &file, expected, input),
+        TestKind::Success => run_success_test(name, &file, expected, &args),
+        TestKind::RuntimeError => run_runtime_error_test(name, &file, expected, &args, exit_code),
         TestKind::StaticError => run_static_error_test(name, &file, expected),
     }
 }
 
-fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
+fn run_success_test(name: &str, file: &Path, expected: &str, args: &[&str]) {
     if let Err(err) = compile(name, file) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input) {
-        Err(err) => {
+    match run(name, args) {
+        Err((err, _)) => {
             panic!("expected a successful execution, but got an error: `{err}`");
         }
         Ok(actual_output) => {
@@ -80,15 +92,30 @@ fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>
     }
 }
 
-fn run_runtime_error_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
+fn run_runtime_error_test(
+    name: &str,
+    file: &Path,
+    expected: &str,
+    args: &[&str],
+    exit_code: Option<i32>,
+) {
     if let Err(err) = compile(name, file) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input) {
+    match run(name, args) {
         Ok(out) => {
             panic!("expected a runtime error, but program executed succesfully - expected error: `{expected}`, output: `{out}`");
         }
-        Err(err) => check_error_msg(&err, expected),
+        Err((err, code)) => {
+            check_error_msg(&err, expected);
+            if let Some(exit_code) = exit_code {
+                assert_eq!(
+                    code,
+                    Some(exit_code),
+                    "the program exited with the wrong exit code"
+                );
+            }
+        }
     }
 }
 
@@ -103,38 +130,179 @@ fn run_static_error_test(name: &str, file: &Path, expected: &str) {
     }
 }
 
+/// Compares what the compiler emits for every tests/*.snek program with the
+/// copies kept in tests/snapshots/: the parsed program (`--emit ast`, as
+/// <name>.ast) and the generated assembly (<name>.s). Any difference fails, and
+/// so does a missing snapshot; with UPDATE_SNAPSHOTS=1, snapshots are written
+/// from the current output instead. Programs with static errors are skipped,
+/// since they have neither.
+pub(crate) fn check_snapshots() {
+    let update = std::env::var("UPDATE_SNAPSHOTS").is_ok_and(|v| v == "1");
+    let snapshots = Path::new("tests").join("snapshots");
+    std::fs::create_dir_all(&snapshots).expect("could not create tests/snapshots");
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir("tests")
+        .expect("could not read tests/")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "snek"))
+        .collect();
+    files.sort();
+
+    let mut changed = vec![];
+    let mut missing = vec![];
+    for file in files {
+        for (ext, emit) in [("ast", "ast"), ("s", "asm")] {
+            let output = Command::new(compiler())
+                .arg(&file)
+                .args(["--emit", emit])
+                .output()
+                .expect("could not run the compiler");
+            if !output.status.success() {
+                continue;
+            }
+            let actual = String::from_utf8(output.stdout).unwrap();
+            let snapshot = snapshots.join(file.with_extension(ext).file_name().unwrap());
+            match std::fs::read_to_string(&snapshot) {
+                Ok(expected) if expected == actual => {}
+                _ if update => {
+                    std::fs::write(&snapshot, &actual).expect("could not write the snapshot")
+                }
+                Ok(expected) => {
+                    eprintln!(
+                        "{} changed!\n{}",
+                        snapshot.display(),
+                        prettydiff::diff_lines(&expected, &actual)
+                    );
+                    changed.push(snapshot.display().to_string());
+                }
+                Err(_) => missing.push(snapshot.display().to_string()),
+            }
+        }
+    }
+    assert!(
+        missing.is_empty(),
+        "missing snapshots: {} - rerun with UPDATE_SNAPSHOTS=1 to create them",
+        missing.join(", ")
+    );
+    assert!(
+        changed.is_empty(),
+        "compiler output differs from the snapshots: {} - rerun with UPDATE_SNAPSHOTS=1 if the change is intended",
+        changed.join(", ")
+    );
+}
+
+/// Runs `check` on a program with several errors, in both error formats, and
+/// checks that it fails with every error reported on a line of its own, in
+/// order. Compiling the program must not write an output file either.
+pub(crate) fn check_all_errors(name: &str, file: &str, expected: &[&str]) {
+    let file = Path::new("tests").join(file);
+    for format in ["human", "json"] {
+        let output = Command::new(compiler())
+            .args(["check", "--error-format", format])
+            .arg(&file)
+            .output()
+            .expect("could not run the compiler");
+        assert!(
+            !output.status.success(),
+            "expected check to fail with {format} errors, but it succeeded"
+        );
+        let errors = String::from_utf8(output.stderr).unwrap();
+        let lines: Vec<&str> = errors.lines().collect();
+        assert_eq!(
+            lines.len(),
+            expected.len(),
+            "expected one error per line, found: `{errors}`"
+        );
+        for (line, expected) in lines.iter().zip(expected) {
+            if format == "json" {
+                assert!(
+                    line.starts_with("{\"code\": "),
+                    "not a JSON error: `{line}`"
+                );
+            }
+            check_error_msg(line, expected);
+        }
+    }
+
+    let asm = mk_path(name, Ext::Asm);
+    let _ = std::fs::remove_file(&asm);
+    let output = Command::new(compiler())
+        .arg(&file)
+        .arg("-o")
+        .arg(&asm)
+        .output()
+        .expect("could not run the compiler");
+    assert!(!output.status.success(), "expected compilation to fail");
+    assert!(!asm.exists(), "{} was written", asm.display());
+}
+
+/// Runs the compiler's `--test-dir` runner on `dir` and checks the tests it
+/// failed and the summary it ends with. It must exit with status 1 if any
+/// test failed.
+pub(crate) fn check_test_dir(dir: &str, failed: &[&str], summary: &str) {
+    let output = Command::new(compiler())
+        .args(["--test-dir", dir])
+        .output()
+        .expect("could not run the compiler");
+    let report = String::from_utf8(output.stdout).unwrap();
+    let actual_failed: Vec<&str> = report
+        .lines()
+        .filter_map(|line| line.strip_prefix("FAIL "))
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(actual_failed, failed, "wrong tests failed:\n{report}");
+    assert_eq!(report.lines().last(), Some(summary), "wrong summary");
+    assert_eq!(
+        output.status.success(),
+        failed.is_empty(),
+        "wrong exit status"
+    );
+}
+
+fn compiler() -> PathBuf {
+    ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect()
+}
+
 fn compile(name: &str, file: &Path) -> Result<(), String> {
     // Run the compiler
-    let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
-    let output = Command::new(&compiler)
+    let output = Command::new(compiler())
         .arg(file)
-        .arg(&mk_path(name, Ext::Asm))
+        .arg(mk_path(name, Ext::Asm))
         .output()
         .expect("could not run the compiler");
     if !output.status.success() {
         return Err(String::from_utf8(output.stderr).unwrap());
     }
 
-    // Assemble and link
+    // Assemble and link. The errors of a failed link are kept next to the assembly, where the
+    // study's changelog can pick them up.
     let output = Command::new("make")
-        .arg(&mk_path(name, Ext::Run))
+        .arg(mk_path(name, Ext::Run))
         .output()
         .expect("could not run make");
-    assert!(output.status.success(), "linking failed");
+    let link_log = mk_path(name, Ext::LinkLog);
+    if !output.status.success() {
+        let log = [output.stdout, output.stderr].concat();
+        let _ = std::fs::write(&link_log, &log);
+        panic!("linking failed:\n{}", String::from_utf8_lossy(&log));
+    }
+    let _ = std::fs::remove_file(&link_log);
 
     Ok(())
 }
 
-fn run(name: &str, input: Option<&str>) -> Result<String, String> {
-    let mut cmd = Command::new(&mk_path(name, Ext::Run));
-    if let Some(input) = input {
-        cmd.arg(input);
-    }
-    let output = cmd.output().unwrap();
+fn run(name: &str, args: &[&str]) -> Result<String, (String, Option<i32>)> {
+    let output = Command::new(mk_path(name, Ext::Run))
+        .args(args)
+        .output()
+        .unwrap();
     if output.status.success() {
         Ok(String::from_utf8(output.stdout).unwrap().trim().to_string())
     } else {
-        Err(String::from_utf8(output.stderr).unwrap().trim().to_string())
+        Err((
+            String::from_utf8(output.stderr).unwrap().trim().to_string(),
+            output.status.code(),
+        ))
     }
 }
 
@@ -169,6 +337,7 @@ fn mk_path(name: &str, ext: Ext) -> PathBuf {
 enum Ext {
     Asm,
     Run,
+    LinkLog,
 }
 
 impl std::fmt::Display for Ext {
@@ -176,6 +345,7 @@ impl std::fmt::Display for Ext {
         match self {
             Ext::Asm => write!(f, "s"),
             Ext::Run => write!(f, "run"),
+            Ext::LinkLog => write!(f, "link.log"),
         }
     }
 }
@@ -0,0 +1,7 @@
+
+section .text
+extern snek_error
+global our_code_starts_here
+our_code_starts_here:
+  mov rax, 131
+  ret